@@ -0,0 +1,126 @@
+/// Words are kept exactly as typed (quotes and escapes included) and only
+/// expanded right before the command runs, so `$?` and friends see the
+/// state left by the previous command of the same line.
+pub type Word = String;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RedirectOp {
+    /// `>`, refuses to overwrite a file with `set -o noclobber`
+    Write,
+    /// `>|`, overwrites whatever `noclobber` says
+    Clobber,
+    /// `>>`
+    Append,
+    /// `2>&1`: the target is the descriptor to copy.
+    Duplicate,
+    /// `&>` and `&>>`: stdout and stderr into the same file.
+    Both { append: bool },
+    /// `<`
+    Read,
+    /// `<< EOF`, the target is the body; a quoted delimiter turns off the
+    /// expansion of `$` inside it.
+    HereDoc { expand: bool },
+    /// `<<< word`
+    HereString,
+}
+
+#[derive(Clone)]
+pub struct Redirect {
+    pub fd: u32,
+    pub op: RedirectOp,
+    pub target: Word,
+}
+
+/// `NAME=value ... command args > redirects`, where every part is optional
+/// as long as there is one.
+#[derive(Clone)]
+pub struct SimpleCommand {
+    pub assignments: Vec<(String, Word)>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+/// `if`, loops and `case`: commands made of whole lists.
+#[derive(Clone)]
+pub enum Compound {
+    /// `{ A; B; }`
+    Group(List),
+    /// `if A; then B; elif C; then D; else E; fi`
+    If {
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    /// `while A; do B; done`, or `until` when `until` is set.
+    Loop {
+        until: bool,
+        condition: List,
+        body: List,
+    },
+    /// `for NAME in WORDS; do BODY; done`, without `in` it goes over `"$@"`.
+    For {
+        name: String,
+        words: Option<Vec<Word>>,
+        body: List,
+    },
+    /// `case WORD in PATTERN|PATTERN) BODY;; ... esac`
+    Case { word: Word, arms: Vec<CaseArm> },
+}
+
+#[derive(Clone)]
+pub struct CaseArm {
+    pub patterns: Vec<Word>,
+    pub body: List,
+}
+
+/// One stage of a pipeline. A compound command can be redirected as a
+/// whole: `while read x; do ...; done < file`.
+#[derive(Clone)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(Compound, Vec<Redirect>),
+    /// `name() { ...; }`: running it only defines the function, the body
+    /// is always a compound command.
+    Function {
+        name: String,
+        body: Box<Command>,
+    },
+}
+
+/// `first | second | ...`, with the source text it was parsed from (the
+/// name of the job Ctrl-Z makes of it).
+#[derive(Clone)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    pub text: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+/// `first && second || third ...`
+#[derive(Clone)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+/// One element of a `;`/`&`/newline separated list, with the source text it
+/// was parsed from (shown when a background job ends).
+#[derive(Clone)]
+pub struct ListItem {
+    pub and_or: AndOr,
+    pub background: bool,
+    pub text: String,
+}
+
+pub type List = Vec<ListItem>;
+
+impl RedirectOp {
+    /// Redirections that replace what the command reads.
+    pub fn is_input(self) -> bool {
+        matches!(self, Self::Read | Self::HereDoc { .. } | Self::HereString)
+    }
+}
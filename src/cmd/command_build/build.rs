@@ -1,15 +1,20 @@
 use std::{
-    io::{self, PipeReader},
+    io,
     path::{Path, PathBuf},
 };
 
 use super::command::{Command, CommandError};
+use super::plumbing::InputStream;
 
 pub trait CommandBuild<'a, E> {
+    /// Every option `new_obj` understands, as typed; what Tab completes
+    /// after the command name.
+    const FLAGS: &'static [&'static str];
+
     fn new_obj(
         args: Vec<&'a str>,
         path: &'a Path,
-        pipe: Option<&'a PipeReader>,
+        pipe: Option<&'a InputStream>,
     ) -> Result<Box<dyn Command<'a, E> + 'a>, CommandError<'a, E>>;
 }
 
@@ -18,4 +23,5 @@ pub enum BuildError<'a> {
     NoArgument(&'a str),
     UnopenedFile(PathBuf, io::Error),
     PipeError(io::Error),
+    BadDescriptor(u32),
 }
@@ -1,10 +1,116 @@
 use super::build::BuildError;
 use super::parse::{CommandBackPack, InputFile};
 use std::{
+    cell::RefCell,
     fmt,
-    io::{self, Read},
+    io::{self, IsTerminal, Read},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
+pub type Status = i32;
+
+pub const SUCCESS: Status = 0;
+pub const FAILURE: Status = 1;
+pub const USAGE: Status = 2;
+pub const NOT_EXECUTABLE: Status = 126;
+pub const NOT_FOUND: Status = 127;
+pub const INTERRUPTED: Status = 130;
+
+/// The Ctrl-C of the commands typed at the prompt, set from the signal
+/// handler of the shell.
+static INTERRUPT: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static WATCHED: RefCell<Cancel> = RefCell::new(Cancel::default());
+}
+
+/// Asks the builtins running for the commands typed at the prompt (the
+/// default one) or for a job to stop: they check it between lines, and the
+/// command reports `INTERRUPTED` (`128 + signal` when `kill` sent one).
+#[derive(Clone, Default)]
+pub struct Cancel(Option<Arc<AtomicBool>>);
+
+impl Cancel {
+    pub fn job() -> Self {
+        Self(Some(Arc::default()))
+    }
+
+    fn flag(&self) -> &AtomicBool {
+        self.0.as_deref().unwrap_or(&INTERRUPT)
+    }
+
+    pub fn set(&self) {
+        self.flag().store(true, Ordering::SeqCst);
+    }
+
+    pub fn clear(&self) {
+        self.flag().store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.flag().load(Ordering::SeqCst)
+    }
+
+    /// The builtins this thread runs from now on check this one.
+    pub fn watch(&self) {
+        WATCHED.with(|watched| *watched.borrow_mut() = self.clone());
+    }
+}
+
+/// Whether the builtin running in this thread was asked to stop.
+pub fn cancelled() -> bool {
+    WATCHED.with(|watched| watched.borrow().is_set())
+}
+
+/// Reads like `R` until the builtin is cancelled, then ends like a file;
+/// so does a read waiting for the terminal that Ctrl-C interrupts.
+struct Cancellable<R>(R);
+
+impl<R: Read> Read for Cancellable<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if cancelled() {
+            return Ok(0);
+        }
+        match self.0.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted && cancelled() => Ok(0),
+            result => result,
+        }
+    }
+}
+
+/// Standard input: a builtin waiting for the terminal wakes up now and
+/// then to see whether it was cancelled.
+struct Stdin(io::Stdin);
+
+impl Read for Stdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_terminal() {
+            let mut fd = libc::pollfd {
+                fd: libc::STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            loop {
+                if cancelled() {
+                    return Ok(0);
+                }
+                // SAFETY: polls one descriptor, described by plain data
+                match unsafe { libc::poll(&mut fd, 1, 100) } {
+                    0 => continue,
+                    -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {
+                        continue;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        self.0.read(buf)
+    }
+}
+
 pub enum CommandError<'a, E> {
     WriteError(io::Error),
     BuildError(BuildError<'a>),
@@ -18,21 +124,39 @@ impl<E> From<io::Error> for CommandError<'_, E> {
     }
 }
 
+/// Exit status reported when a command fails with this error.
+pub trait ErrorStatus {
+    fn status(&self) -> Status {
+        FAILURE
+    }
+}
+
+impl<E: ErrorStatus> CommandError<'_, E> {
+    pub fn status(&self) -> Status {
+        match self {
+            Self::Help => SUCCESS,
+            Self::BuildError(_) => USAGE,
+            Self::WriteError(_) => FAILURE,
+            Self::Other(_, e) => e.status(),
+        }
+    }
+}
+
 pub trait Command<'a, E> {
     fn help() -> ()
     where
         Self: Sized;
-    fn run(self: Box<Self>, output: &mut CommandBackPack<'a>) -> Result<bool, CommandError<'a, E>>;
+    fn run(self: Box<Self>, output: &mut CommandBackPack) -> Result<Status, CommandError<'a, E>>;
     fn input_type(file: &InputFile<'a>) -> Result<Box<dyn Read + 'a>, CommandError<'a, E>>
     where
         Self: Sized,
     {
         match file {
-            InputFile::Pipe(pipe_read) => Ok(Box::new(*pipe_read)),
-            InputFile::Stdin => Ok(Box::new(io::stdin())),
+            InputFile::Pipe(pipe_read) => Ok(Box::new(Cancellable(*pipe_read))),
+            InputFile::Stdin => Ok(Box::new(Cancellable(Stdin(io::stdin())))),
             InputFile::File(path, filename) => {
                 match CommandBackPack::read_in_file(path, filename) {
-                    Ok(file) => Ok(Box::new(file)),
+                    Ok(file) => Ok(Box::new(Cancellable(file))),
                     Err(e) => Err(CommandError::BuildError(e)),
                 }
             }
@@ -49,6 +173,7 @@ impl fmt::Display for BuildError<'_> {
                 writeln!(f, "shu: can't open the file ({}): {}", n.display(), s)
             }
             Self::NoArgument(s) => writeln!(f, "shu: no argument after: {}", s),
+            Self::BadDescriptor(fd) => writeln!(f, "shu: bad file descriptor: {}", fd),
         }
     }
 }
@@ -0,0 +1,510 @@
+use super::glob::has_magic;
+use std::{fmt, iter::Peekable, path::Path, str::Chars};
+
+/// Where `$NAME` gets its value from.
+pub trait Variables {
+    fn var(&self, name: &str) -> Option<String>;
+
+    /// `$1`, `$2`...: what `"$@"` expands to, one field each.
+    fn params(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// A parameter that isn't set is an error instead of empty: `set -u`.
+    fn nounset(&self) -> bool {
+        false
+    }
+}
+
+/// How unquoted `*`, `?` and `[...]` are expanded into file names.
+pub struct Glob<'a> {
+    /// Relative patterns are matched from here.
+    pub dir: &'a Path,
+    /// A pattern without matches is an error instead of staying as typed.
+    pub fail: bool,
+}
+
+pub enum ExpandError {
+    NoMatch(String),
+    Unbound(String),
+    /// A `${...}` that isn't one of the forms known, or isn't closed.
+    BadSubstitution(String),
+}
+
+/// Turns a word as typed into the arguments a command gets: brace
+/// expansion first, then parameters, field splitting, globbing and quote
+/// removal. Without `glob` the wildcards stay as they are.
+pub fn expand_word<V: Variables + ?Sized>(
+    word: &str,
+    vars: &V,
+    glob: Option<&Glob>,
+) -> Result<Vec<String>, ExpandError> {
+    let mut fields = Vec::new();
+    for word in expand_braces(word) {
+        fields.extend(expand_params(&word, vars, true, glob)?);
+    }
+    Ok(fields)
+}
+
+/// Expansion for the right side of `NAME=value`: no brace expansion, no
+/// splitting and no globbing, the result is always a single string.
+pub fn expand_value<V: Variables + ?Sized>(word: &str, vars: &V) -> Result<String, ExpandError> {
+    Ok(expand_params(word, vars, false, None)?.join(" "))
+}
+
+/// Expansion inside a here-document body: only parameters, and a
+/// backslash only escapes `$`, `` ` ``, `\\` and the end of a line. Quotes
+/// stay as they are.
+pub fn expand_here_doc<V: Variables + ?Sized>(body: &str, vars: &V) -> Result<String, ExpandError> {
+    let mut result = String::new();
+    let mut chars = body.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next_if(|c| matches!(c, '$' | '`' | '\\' | '\n')) {
+                Some('\n') => {}
+                Some(ch) => result.push(ch),
+                None => result.push('\\'),
+            },
+            '$' => match parameter(&mut chars, vars)? {
+                Some(value) => result.push_str(&value),
+                None => result.push('$'),
+            },
+            _ => result.push(ch),
+        }
+    }
+    Ok(result)
+}
+
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// Splits `NAME=value` if the word is an assignment.
+pub fn assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_once('=')?;
+    is_name(name).then_some((name, value))
+}
+
+/// Byte offsets of the characters that are neither quoted nor escaped,
+/// the only ones that can have a special meaning.
+fn unquoted(word: &str) -> Vec<(usize, char)> {
+    let mut marks = Vec::new();
+    let mut quote = None;
+    let mut chars = word.char_indices();
+    while let Some((i, ch)) = chars.next() {
+        match (quote, ch) {
+            (None | Some('"'), '\\') => {
+                chars.next();
+            }
+            (None, '\'' | '"') => quote = Some(ch),
+            (Some(q), ch) if q == ch => quote = None,
+            (Some(_), _) => {}
+            (None, ch) => marks.push((i, ch)),
+        }
+    }
+    marks
+}
+
+fn expand_braces(word: &str) -> Vec<String> {
+    let marks = unquoted(word);
+    for (index, &(start, ch)) in marks.iter().enumerate() {
+        // `${NAME}` is a parameter, not a brace list
+        if ch != '{' || (index > 0 && marks[index - 1] == (start - 1, '$')) {
+            continue;
+        }
+        let mut depth = 0;
+        let mut end = None;
+        let mut separators = Vec::new();
+        for &(i, ch) in &marks[index..] {
+            match ch {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = Some(i);
+                        break;
+                    }
+                }
+                ',' | ' ' | '\t' if depth == 1 => separators.push(i),
+                _ => {}
+            }
+        }
+        let Some(end) = end else {
+            continue;
+        };
+        if separators.is_empty() {
+            continue;
+        }
+
+        let prefix = &word[..start];
+        let suffix = &word[end + 1..];
+        let mut result = Vec::new();
+        let mut from = start + 1;
+        for to in separators.into_iter().chain([end]) {
+            let part = &word[from..to];
+            from = to + 1;
+            if !part.is_empty() {
+                result.extend(expand_braces(&format!("{}{}{}", prefix, part, suffix)));
+            }
+        }
+        return result;
+    }
+    vec![word.to_string()]
+}
+
+/// The fields a word is being expanded into.
+struct Fields<'g> {
+    fields: Vec<String>,
+    current: String,
+    /// `current` as a glob pattern, with the quoted wildcards escaped.
+    pattern: String,
+    /// A quoted empty string (`""`) is still an argument.
+    quoted: bool,
+    glob: Option<&'g Glob<'g>>,
+}
+
+impl<'g> Fields<'g> {
+    fn new(glob: Option<&'g Glob<'g>>) -> Self {
+        Self {
+            fields: Vec::new(),
+            current: String::new(),
+            pattern: String::new(),
+            quoted: false,
+            glob,
+        }
+    }
+
+    fn push(&mut self, ch: char) {
+        self.current.push(ch);
+        self.pattern.push(ch);
+    }
+
+    fn push_quoted(&mut self, ch: char) {
+        self.current.push(ch);
+        if matches!(ch, '*' | '?' | '[' | ']' | '\\') {
+            self.pattern.push('\\');
+        }
+        self.pattern.push(ch);
+    }
+
+    /// Value of an unquoted parameter: blanks in it separate fields.
+    fn push_split(&mut self, value: &str) -> Result<(), ExpandError> {
+        for (i, part) in value.split([' ', '\t', '\n']).enumerate() {
+            if i > 0 {
+                self.finish()?;
+            }
+            part.chars().for_each(|ch| self.push(ch));
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ExpandError> {
+        let current = std::mem::take(&mut self.current);
+        let pattern = std::mem::take(&mut self.pattern);
+        if let Some(glob) = self.glob
+            && has_magic(&pattern)
+        {
+            let found = super::glob::glob(&pattern, glob.dir);
+            if !found.is_empty() {
+                self.fields.extend(found);
+            } else if glob.fail {
+                return Err(ExpandError::NoMatch(current));
+            } else {
+                self.fields.push(current);
+            }
+        } else if !current.is_empty() || self.quoted {
+            self.fields.push(current);
+        }
+        self.quoted = false;
+        Ok(())
+    }
+}
+
+/// A `case` pattern: parameters are expanded, quoted wildcards are escaped
+/// so they only match themselves; those of an unquoted `$var` still match.
+pub fn expand_pattern<V: Variables + ?Sized>(word: &str, vars: &V) -> Result<String, ExpandError> {
+    let mut fields = Fields::new(None);
+    scan(&mut fields, word, vars, false)?;
+    Ok(fields.pattern)
+}
+
+fn expand_params<V: Variables + ?Sized>(
+    word: &str,
+    vars: &V,
+    split: bool,
+    glob: Option<&Glob>,
+) -> Result<Vec<String>, ExpandError> {
+    let mut fields = Fields::new(glob);
+    scan(&mut fields, word, vars, split)?;
+    fields.finish()?;
+    Ok(fields.fields)
+}
+
+/// Goes through the characters of a word: quote removal and parameters.
+fn scan<V: Variables + ?Sized>(
+    fields: &mut Fields,
+    word: &str,
+    vars: &V,
+    split: bool,
+) -> Result<(), ExpandError> {
+    let mut quote = None;
+    let mut chars = word.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match (quote, ch) {
+            (None, '\'' | '"') => {
+                quote = Some(ch);
+                fields.quoted = true;
+            }
+            (Some(q), ch) if q == ch => quote = None,
+            (Some('\''), ch) => fields.push_quoted(ch),
+            (None, '\\') => chars
+                .next()
+                .into_iter()
+                .for_each(|ch| fields.push_quoted(ch)),
+            (Some(_), '\\') => match chars.next_if(|c| matches!(c, '$' | '`' | '"' | '\\')) {
+                Some(ch) => fields.push_quoted(ch),
+                None => fields.push_quoted('\\'),
+            },
+            (Some(_), '$') if chars.next_if_eq(&'@').is_some() => {
+                let params = vars.params();
+                if params.is_empty() {
+                    // a lone `"$@"` without parameters is no argument at all
+                    fields.quoted = false;
+                }
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        fields.finish()?;
+                        fields.quoted = true;
+                    }
+                    param.chars().for_each(|ch| fields.push_quoted(ch));
+                }
+            }
+            (_, '$') => match parameter(&mut chars, vars)? {
+                Some(value) if split && quote.is_none() => fields.push_split(&value)?,
+                // unquoted, its wildcards still match in a `case` pattern
+                Some(value) if quote.is_none() => value.chars().for_each(|ch| fields.push(ch)),
+                Some(value) => value.chars().for_each(|ch| fields.push_quoted(ch)),
+                None => fields.push_quoted('$'),
+            },
+            (Some(_), ch) => fields.push_quoted(ch),
+            (None, ch) => fields.push(ch),
+        }
+    }
+    Ok(())
+}
+
+/// Reads the parameter after a `$`: `$NAME`, `$1`, `$?`, `$#`, `${NAME}`,
+/// `${NAME:-default}` or `${NAME-default}`. `None` means the `$` was just
+/// a dollar sign; any other `${...}` is a bad substitution.
+fn parameter<V: Variables + ?Sized>(
+    chars: &mut Peekable<Chars>,
+    vars: &V,
+) -> Result<Option<String>, ExpandError> {
+    if chars.next_if_eq(&'{').is_some() {
+        let mut inside = String::new();
+        let mut depth = 1;
+        for ch in chars.by_ref() {
+            match ch {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            inside.push(ch);
+        }
+        if depth > 0 {
+            return Err(ExpandError::BadSubstitution(format!("${{{}", inside)));
+        }
+        let (name, operator) = inside.split_at(name_len(&inside));
+        let known = operator.is_empty() || operator.starts_with('-') || operator.starts_with(":-");
+        if name.is_empty() || !known {
+            return Err(ExpandError::BadSubstitution(format!("${{{}}}", inside)));
+        }
+        let value = vars.var(name);
+        return Ok(Some(if let Some(default) = operator.strip_prefix(":-") {
+            match value {
+                Some(value) if !value.is_empty() => value,
+                _ => expand_value(default, vars)?,
+            }
+        } else if let Some(default) = operator.strip_prefix('-') {
+            match value {
+                Some(value) => value,
+                None => expand_value(default, vars)?,
+            }
+        } else {
+            set_or_empty(name, value, vars)?
+        }));
+    }
+    if let Some(special) =
+        chars.next_if(|c| matches!(c, '?' | '$' | '#' | '@' | '*') || c.is_ascii_digit())
+    {
+        let name = special.to_string();
+        let value = vars.var(&name);
+        return set_or_empty(&name, value, vars).map(Some);
+    }
+    let mut name = String::new();
+    while let Some(ch) = chars.next_if(|&c| {
+        c == '_' || c.is_ascii_alphabetic() || (!name.is_empty() && c.is_ascii_digit())
+    }) {
+        name.push(ch);
+    }
+    if name.is_empty() {
+        Ok(None)
+    } else {
+        let value = vars.var(&name);
+        set_or_empty(&name, value, vars).map(Some)
+    }
+}
+
+/// How long the name at the start of a `${...}` is: a variable name, the
+/// digits of a positional parameter or one special character; 0 if there is
+/// none.
+fn name_len(inside: &str) -> usize {
+    match inside.chars().next() {
+        Some(c) if c.is_ascii_digit() => inside
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(inside.len()),
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => inside
+            .find(|c: char| !(c == '_' || c.is_ascii_alphanumeric()))
+            .unwrap_or(inside.len()),
+        Some('?' | '$' | '#' | '@' | '*') => 1,
+        _ => 0,
+    }
+}
+
+/// The value of a parameter, empty when it isn't set (or an error, with
+/// `set -u`).
+fn set_or_empty<V: Variables + ?Sized>(
+    name: &str,
+    value: Option<String>,
+    vars: &V,
+) -> Result<String, ExpandError> {
+    match value {
+        Some(value) => Ok(value),
+        None if vars.nounset() => Err(ExpandError::Unbound(name.to_string())),
+        None => Ok(String::new()),
+    }
+}
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoMatch(pattern) => write!(f, "shu: no match: {}", pattern),
+            Self::Unbound(name) => write!(f, "shu: {}: unbound variable", name),
+            Self::BadSubstitution(text) => write!(f, "shu: {}: bad substitution", text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct Vars(HashMap<&'static str, &'static str>, bool);
+
+    impl Variables for Vars {
+        fn var(&self, name: &str) -> Option<String> {
+            self.0.get(name).map(|value| value.to_string())
+        }
+
+        fn nounset(&self) -> bool {
+            self.1
+        }
+    }
+
+    fn vars() -> Vars {
+        Vars(
+            HashMap::from([("X", "1"), ("E", ""), ("1", "one"), ("#", "1")]),
+            false,
+        )
+    }
+
+    fn value(word: &str) -> Result<String, String> {
+        expand_value(word, &vars()).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn parameters_and_defaults() {
+        assert_eq!(value("$X-${X}y").unwrap(), "1-1y");
+        assert_eq!(value("${1}|$1|${#}|$#").unwrap(), "one|one|1|1");
+        assert_eq!(value("${E:-d}|${E-d}|${U-d}|${U:-$X}").unwrap(), "d||d|1");
+        assert_eq!(value("${U}$U").unwrap(), "");
+        assert_eq!(value("a$ b").unwrap(), "a$ b");
+    }
+
+    fn words(word: &str) -> Vec<String> {
+        expand_word(word, &vars(), None).unwrap_or_default()
+    }
+
+    #[test]
+    fn braces_then_parameters_and_splitting() {
+        assert_eq!(words("a{b,c}d"), ["abd", "acd"]);
+        assert_eq!(words("{x,y{1,2}}"), ["x", "y1", "y2"]);
+        assert_eq!(words("{a}"), ["{a}"]);
+        assert_eq!(words("'{a,b}'"), ["{a,b}"]);
+        assert_eq!(words("${X}{a,b}"), ["1a", "1b"]);
+        assert_eq!(words("\"\"$E"), [""]);
+        assert!(words("$E").is_empty());
+    }
+
+    #[test]
+    fn unquoted_wildcards_glob() {
+        let dir = std::env::temp_dir().join(format!("shu-expand-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.rs"), "").unwrap();
+        std::fs::write(dir.join("b.rs"), "").unwrap();
+        let glob = |fail| Glob { dir: &dir, fail };
+        let found = |word| expand_word(word, &vars(), Some(&glob(false))).ok();
+        let failed = expand_word("*.txt", &vars(), Some(&glob(true)));
+
+        assert_eq!(found("{b,a}.rs"), Some(vec!["b.rs".into(), "a.rs".into()]));
+        assert_eq!(found("*.rs"), Some(vec!["a.rs".into(), "b.rs".into()]));
+        assert_eq!(found("'*'.rs"), Some(vec!["*.rs".into()]));
+        assert_eq!(found("*.txt"), Some(vec!["*.txt".into()]));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(failed, Err(ExpandError::NoMatch(_))));
+    }
+
+    #[test]
+    fn unknown_forms_are_bad_substitutions() {
+        assert_eq!(
+            value("${X:+a}").unwrap_err(),
+            "shu: ${X:+a}: bad substitution"
+        );
+        assert_eq!(value("${#X}").unwrap_err(), "shu: ${#X}: bad substitution");
+        assert_eq!(value("${}").unwrap_err(), "shu: ${}: bad substitution");
+        assert_eq!(value("${X").unwrap_err(), "shu: ${X: bad substitution");
+    }
+
+    #[test]
+    fn case_patterns_keep_unquoted_wildcards() {
+        let vars = Vars(HashMap::from([("P", "a*")]), false);
+        assert_eq!(expand_pattern("$P", &vars).ok().as_deref(), Some("a*"));
+        assert_eq!(
+            expand_pattern("\"$P\"", &vars).ok().as_deref(),
+            Some("a\\*")
+        );
+        assert_eq!(expand_pattern("'?'x", &vars).ok().as_deref(), Some("\\?x"));
+        let strict = Vars(HashMap::new(), true);
+        assert!(matches!(
+            expand_pattern("$NOPE", &strict),
+            Err(ExpandError::Unbound(_))
+        ));
+    }
+
+    #[test]
+    fn unset_is_an_error_with_nounset() {
+        let vars = Vars(HashMap::new(), true);
+        let unbound = |word| matches!(expand_value(word, &vars), Err(ExpandError::Unbound(_)));
+        assert!(unbound("$U"));
+        assert!(unbound("${U}"));
+        assert!(!unbound("${U-d}"));
+    }
+}
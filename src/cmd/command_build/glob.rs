@@ -0,0 +1,235 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The pattern has an unescaped `*`, `?` or `[`.
+pub fn has_magic(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn unescape(pattern: &str) -> String {
+    let mut result = String::new();
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => result.extend(chars.next()),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+/// Matches `[...]` starting right after the `[`. Returns whether `ch` is in
+/// the class and where the pattern continues, or `None` if the class is
+/// never closed (then the `[` is an ordinary character).
+fn class(pattern: &[char], ch: char) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negate = matches!(pattern.first(), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+    let mut found = false;
+    let mut first = true;
+    while i < pattern.len() {
+        let mut low = pattern[i];
+        if low == ']' && !first {
+            return Some((found != negate, i + 1));
+        }
+        first = false;
+        if low == '\\' && i + 1 < pattern.len() {
+            i += 1;
+            low = pattern[i];
+        }
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            found |= (low..=pattern[i + 2]).contains(&ch);
+            i += 3;
+        } else {
+            found |= low == ch;
+            i += 1;
+        }
+    }
+    None
+}
+
+fn matches_chars(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // where to resume after the last `*` if the rest doesn't match
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match class(&pattern[p + 1..], name[n]) {
+                Some((true, len)) => Some(p + 1 + len),
+                Some((false, _)) => None,
+                None => (name[n] == '[').then_some(p + 1),
+            },
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == name[n]).then_some(p + 2),
+            Some(&ch) => (ch == name[n]).then_some(p + 1),
+            None => None,
+        };
+        match (step, star) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            }
+            (None, Some((star_p, star_n))) => {
+                p = star_p;
+                n = star_n + 1;
+                star = Some((star_p, star_n + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
+
+/// Shell pattern matching of a single name: `*`, `?`, `[a-z]`, `[!x]` and
+/// backslash escapes.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_chars(&pattern, &name)
+}
+
+fn join(shown: &str, name: &str) -> String {
+    if shown.is_empty() {
+        name.to_string()
+    } else if shown.ends_with('/') {
+        format!("{}{}", shown, name)
+    } else {
+        format!("{}/{}", shown, name)
+    }
+}
+
+fn entries(path: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(dir) = fs::read_dir(path) else {
+        return Vec::new();
+    };
+    let mut entries: Vec<_> = dir
+        .filter_map(Result::ok)
+        .filter_map(|entry| Some((entry.file_name().into_string().ok()?, entry.path())))
+        .collect();
+    entries.sort();
+    entries
+}
+
+/// Every directory under `path` that isn't hidden, `path` included.
+/// Symlinks to directories aren't followed, so a link back up the tree
+/// can't send `**` round in circles.
+fn walk(shown: String, path: PathBuf, found: &mut Vec<(String, PathBuf)>) {
+    for (name, entry) in entries(&path) {
+        let is_dir = entry
+            .symlink_metadata()
+            .is_ok_and(|meta| meta.file_type().is_dir());
+        if !name.starts_with('.') && is_dir {
+            walk(join(&shown, &name), entry, found);
+        }
+    }
+    found.push((shown, path));
+}
+
+/// Expands a pattern into the existing paths it matches, sorted. Relative
+/// patterns are matched against `dir` and the results stay relative.
+/// Wildcards never match a leading `.` unless the pattern has one too, and
+/// a `**` component goes through any number of non-hidden directories.
+pub fn glob(pattern: &str, dir: &Path) -> Vec<String> {
+    let (mut found, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec![("/".to_string(), PathBuf::from("/"))], rest),
+        None => (vec![(String::new(), dir.to_path_buf())], pattern),
+    };
+    let components: Vec<&str> = rest.split('/').collect();
+    for (i, component) in components.iter().enumerate() {
+        let last = i + 1 == components.len();
+        let mut next = Vec::new();
+        for (shown, path) in found {
+            if component.is_empty() {
+                // `dir//x` or a trailing `/`: only directories go on
+                if path.is_dir() {
+                    next.push((if last { format!("{}/", shown) } else { shown }, path));
+                }
+            } else if *component == "**" {
+                let mut dirs = Vec::new();
+                walk(shown.clone(), path.clone(), &mut dirs);
+                if last {
+                    for (dir_shown, dir_path) in dirs {
+                        for (name, entry) in entries(&dir_path) {
+                            if !name.starts_with('.') {
+                                next.push((join(&dir_shown, &name), entry));
+                            }
+                        }
+                    }
+                } else {
+                    next.extend(dirs);
+                }
+            } else if !has_magic(component) {
+                let name = unescape(component);
+                let entry = path.join(&name);
+                if entry.symlink_metadata().is_ok() && (last || entry.is_dir()) {
+                    next.push((join(&shown, &name), entry));
+                }
+            } else {
+                for (name, entry) in entries(&path) {
+                    if name.starts_with('.') && !component.starts_with('.') {
+                        continue;
+                    }
+                    if matches(component, &name) && (last || entry.is_dir()) {
+                        next.push((join(&shown, &name), entry));
+                    }
+                }
+            }
+        }
+        found = next;
+    }
+    let mut result: Vec<String> = found
+        .into_iter()
+        .map(|(shown, _)| shown)
+        .filter(|shown| !shown.is_empty())
+        .collect();
+    result.sort();
+    result.dedup();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_wildcards_and_classes() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("a?c", "abc"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(!matches("\\*", "a"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn double_star_skips_symlinked_dirs() {
+        let root = std::env::temp_dir().join(format!("shu-glob-{}", std::process::id()));
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/x.rs"), "").unwrap();
+        fs::write(root.join("y.rs"), "").unwrap();
+        std::os::unix::fs::symlink("..", root.join("a/up")).unwrap();
+
+        let found = glob("**/*.rs", &root);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(found, ["a/x.rs", "y.rs"]);
+    }
+}
@@ -1,3 +1,7 @@
+pub mod ast;
 pub mod build;
 pub mod command;
+pub mod expand;
+pub mod glob;
 pub mod parse;
+pub mod plumbing;
@@ -1,28 +1,109 @@
-use super::build::BuildError;
+use super::{
+    ast::{
+        AndOr, CaseArm, Command, Compound, Connector, List, ListItem, Pipeline, Redirect,
+        RedirectOp, SimpleCommand, Word,
+    },
+    build::BuildError,
+    expand::{assignment, is_name},
+    plumbing::InputStream,
+};
 use std::{
+    collections::HashMap,
+    fmt,
     fs::{File, OpenOptions},
-    io::{self, PipeReader, Write},
+    io::{self, PipeWriter, Write},
+    iter::Peekable,
+    os::fd::AsFd,
     path::Path,
+    process::Stdio,
+    str::CharIndices,
 };
 
-pub struct CommandBackPack<'a> {
-    pub stdout: Box<dyn Write + 'a>,
-    pub stderr: Box<dyn Write + 'a>,
+pub struct CommandBackPack {
+    pub stdout: OutputFile,
+    pub stderr: OutputFile,
+}
+
+pub enum OutputFile {
+    Stdout,
+    Stderr,
+    File(File),
+    Pipe(PipeWriter),
 }
 
 pub enum InputFile<'a> {
     Stdin,
-    Pipe(&'a PipeReader),
+    Pipe(&'a InputStream),
     File(&'a Path, &'a str),
 }
 
 pub struct ParseExit<'a> {
-    pub commandbp: CommandBackPack<'a>,
+    pub commandbp: CommandBackPack,
     pub args_left: Vec<&'a str>,
-    pub pipe_part: (Option<PipeReader>, Option<Vec<&'a str>>),
 }
 
-impl<'a> CommandBackPack<'a> {
+impl OutputFile {
+    pub fn stdio(&self) -> io::Result<Stdio> {
+        Ok(match self {
+            Self::Stdout => Stdio::from(io::stdout().as_fd().try_clone_to_owned()?),
+            Self::Stderr => Stdio::from(io::stderr().as_fd().try_clone_to_owned()?),
+            Self::File(file) => Stdio::from(file.try_clone()?),
+            Self::Pipe(pipe) => Stdio::from(pipe.try_clone()?),
+        })
+    }
+}
+
+impl Default for CommandBackPack {
+    fn default() -> Self {
+        Self {
+            stdout: OutputFile::Stdout,
+            stderr: OutputFile::Stderr,
+        }
+    }
+}
+
+impl CommandBackPack {
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            stdout: self.stdout.try_clone()?,
+            stderr: self.stderr.try_clone()?,
+        })
+    }
+}
+
+impl OutputFile {
+    /// Another handle to the same sink, so `2>&1` writes land in order.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Self::Stdout => Self::Stdout,
+            Self::Stderr => Self::Stderr,
+            Self::File(file) => Self::File(file.try_clone()?),
+            Self::Pipe(pipe) => Self::Pipe(pipe.try_clone()?),
+        })
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Stdout => io::stdout().write(buf),
+            Self::Stderr => io::stderr().write(buf),
+            Self::File(file) => file.write(buf),
+            Self::Pipe(pipe) => pipe.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout => io::stdout().flush(),
+            Self::Stderr => io::stderr().flush(),
+            Self::File(file) => file.flush(),
+            Self::Pipe(pipe) => pipe.flush(),
+        }
+    }
+}
+
+impl<'a> CommandBackPack {
     pub fn read_in_file(path: &Path, filename: &'a str) -> Result<File, BuildError<'a>> {
         let path = path.join(filename);
         match File::open(&path) {
@@ -35,7 +116,7 @@ impl<'a> CommandBackPack<'a> {
         path: &Path,
         filename: &'a str,
         add_mode: bool,
-    ) -> Result<Box<dyn Write + 'a>, BuildError<'a>> {
+    ) -> Result<OutputFile, BuildError<'a>> {
         let path = path.join(filename);
         match OpenOptions::new()
             .append(add_mode)
@@ -44,11 +125,30 @@ impl<'a> CommandBackPack<'a> {
             .truncate(!add_mode)
             .open(&path)
         {
-            Ok(file) => Ok(Box::new(file)),
+            Ok(file) => Ok(OutputFile::File(file)),
             Err(e) => Err(BuildError::UnopenedFile(path, e)),
         }
     }
 
+    /// Opens the target of `>`, `>|`, `>>` or `&>`.
+    fn redirect_file(
+        path: &Path,
+        filename: &'a str,
+        op: RedirectOp,
+    ) -> Result<OutputFile, BuildError<'a>> {
+        let full = path.join(filename);
+        // noclobber only protects regular files, `> /dev/null` is fine
+        if op == RedirectOp::Write && full.is_file() {
+            let e = io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "cannot overwrite existing file (noclobber)",
+            );
+            return Err(BuildError::UnopenedFile(full, e));
+        }
+        let append = matches!(op, RedirectOp::Append | RedirectOp::Both { append: true });
+        Self::read_out_file(path, filename, append)
+    }
+
     pub fn get_next<'b>(args: &'b [&'a str], i: usize) -> Result<&'a str, BuildError<'a>> {
         if i + 1 >= args.len() {
             Err(BuildError::NoArgument(args[i]))
@@ -57,180 +157,1073 @@ impl<'a> CommandBackPack<'a> {
         }
     }
 
-    pub fn parser(args: Vec<&'a str>, path: &Path) -> Result<ParseExit<'a>, BuildError<'a>> {
+    /// Applies the redirections and the output flags of a command on top of
+    /// `output`, where it writes otherwise (the terminal, a pipe...).
+    pub fn parser(
+        args: Vec<&'a str>,
+        path: &Path,
+        redirects: &'a [Redirect],
+        output: Self,
+    ) -> Result<ParseExit<'a>, BuildError<'a>> {
         let mut args_left = Vec::new();
         let mut i: usize = 1;
         let mut stdout_name = None;
         let mut stderr_name = None;
-        let mut pipe_part = (None, None);
         let mut add_mode = false;
-        let mut err_add_mode = false;
+        let Self {
+            mut stdout,
+            mut stderr,
+        } = output;
+        // applied left to right: `> log 2>&1` sends both to the log, while
+        // `2>&1 > log` sends stderr where stdout was before
+        // (`<` and here-documents are opened by the caller as the stdin stream)
+        for redirect in redirects.iter().filter(|redirect| !redirect.op.is_input()) {
+            let target = redirect.target.as_str();
+            let sink = match redirect.op {
+                RedirectOp::Duplicate => match target {
+                    "1" => stdout.try_clone(),
+                    "2" => stderr.try_clone(),
+                    _ => match target.parse() {
+                        Ok(fd) => return Err(BuildError::BadDescriptor(fd)),
+                        Err(_) => return Err(BuildError::UnexpectedArg(target)),
+                    },
+                }
+                .map_err(BuildError::PipeError)?,
+                RedirectOp::Both { .. } => {
+                    stdout = Self::redirect_file(path, target, redirect.op)?;
+                    stderr = stdout.try_clone().map_err(BuildError::PipeError)?;
+                    continue;
+                }
+                op => Self::redirect_file(path, target, op)?,
+            };
+            match redirect.fd {
+                1 => stdout = sink,
+                2 => stderr = sink,
+                fd => return Err(BuildError::BadDescriptor(fd)),
+            }
+        }
         while args.len() > i {
             match args[i] {
-                ">" | "--output" | "-out" => {
+                "--output" | "-out" => {
                     stdout_name = Some(Self::get_next(&args, i)?);
                     i += 1;
                 }
-                ">>" => {
-                    stdout_name = Some(Self::get_next(&args, i)?);
-                    i += 1;
-                    add_mode = true;
-                }
-                "|" | "--pipe" | "--pipe-mode" => {
-                    if i + 1 < args.len() {
-                        pipe_part.1 = Some(Vec::from(&args[i + 1..]));
-                        break;
-                    }
-                }
-                "--err" | "--stderr" | "2>" | "--error" => {
-                    stderr_name = Some(Self::get_next(&args, i)?);
-                    i += 1;
-                }
-                "2>>" => {
+                "--err" | "--stderr" | "--error" => {
                     stderr_name = Some(Self::get_next(&args, i)?);
                     i += 1;
-                    err_add_mode = true;
                 }
                 "-add" | "--add-mode" => add_mode = true,
                 _ => args_left.push(args[i]),
             }
             i += 1;
         }
+        if let Some(name) = stderr_name {
+            stderr = Self::read_out_file(path, name, false)?;
+        }
+        if let Some(name) = stdout_name {
+            stdout = Self::read_out_file(path, name, add_mode)?;
+        }
         Ok(ParseExit {
-            commandbp: Self {
-                stderr: if let Some(name) = stderr_name {
-                    Box::new(Self::read_out_file(path, name, err_add_mode)?)
-                } else {
-                    Box::new(io::stderr())
-                },
-                stdout: if pipe_part.1.is_some() {
-                    match io::pipe() {
-                        Ok((pipe_re, pipe_wr)) => {
-                            pipe_part.0 = Some(pipe_re);
-                            Box::new(pipe_wr)
-                        }
-                        Err(e) => return Err(BuildError::PipeError(e)),
-                    }
-                } else if let Some(name) = stdout_name {
-                    Box::new(Self::read_out_file(path, name, add_mode)?)
-                } else {
-                    Box::new(io::stdout())
-                },
-            },
+            commandbp: Self { stdout, stderr },
             args_left,
-            pipe_part,
         })
     }
 }
 
-pub fn split_args(command: &str) -> Vec<String> {
-    let mut args = Vec::new();
+#[derive(Clone, PartialEq)]
+pub enum Token {
+    Word(Word),
+    Redirect {
+        fd: u32,
+        op: RedirectOp,
+    },
+    Pipe,
+    And,
+    Or,
+    Semi,
+    /// `;;` ending a `case` arm
+    DoubleSemi,
+    LParen,
+    RParen,
+    Amp,
+    Newline,
+}
+
+/// A token with the byte range of the line it was read from.
+pub struct Spanned {
+    pub token: Token,
+    pub start: usize,
+    pub end: usize,
+}
+
+pub enum ParseError {
+    UnexpectedToken(String),
+    UnclosedQuote(char),
+    /// The input ended before the delimiter line of a here-document.
+    UnclosedHereDoc(String),
+    Incomplete,
+}
+
+impl ParseError {
+    /// The line can still become valid if more input is appended to it.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            Self::UnclosedQuote(_) | Self::UnclosedHereDoc(_) | Self::Incomplete
+        )
+    }
+}
+
+fn push_word(tokens: &mut Vec<Spanned>, current: &mut String, start: usize, end: usize) {
+    if current.is_empty() {
+        return;
+    }
+    let token = match current.as_str() {
+        "--pipe" | "--pipe-mode" => Token::Pipe,
+        _ => Token::Word(current.clone()),
+    };
+    tokens.push(Spanned { token, start, end });
+    current.clear();
+}
+
+/// The delimiter of a here-document without its quotes, and whether the
+/// body is expanded (only if no part of the delimiter was quoted).
+fn here_delimiter(word: &str) -> (String, bool) {
+    let delimiter: String = word
+        .chars()
+        .filter(|c| !matches!(c, '\'' | '"' | '\\'))
+        .collect();
+    let expand = delimiter.len() == word.len();
+    (delimiter, expand)
+}
+
+/// Reads the lines of a here-document up to its delimiter line. With
+/// `strip_tabs` (`<<-`) leading tabs are removed from every line.
+fn here_body(
+    chars: &mut Peekable<CharIndices>,
+    delimiter: &str,
+    strip_tabs: bool,
+) -> Result<String, ParseError> {
+    let mut body = String::new();
+    loop {
+        if chars.peek().is_none() {
+            return Err(ParseError::UnclosedHereDoc(delimiter.to_string()));
+        }
+        let mut line = String::new();
+        for (_, ch) in chars.by_ref() {
+            if ch == '\n' {
+                break;
+            }
+            line.push(ch);
+        }
+        let line = if strip_tabs {
+            line.trim_start_matches('\t')
+        } else {
+            &line
+        };
+        if line == delimiter {
+            return Ok(body);
+        }
+        body.push_str(line);
+        body.push('\n');
+    }
+}
+
+/// Splits a line into words and operators. Quotes and backslashes are kept
+/// inside the words (see `expand`), unquoted blanks inside `{...}` do not
+/// split a word, so `mkdir {a b c}` still gets a single brace word.
+///
+/// The body of a here-document starts on the line after its `<<`: it
+/// replaces the delimiter word in the returned tokens.
+pub fn tokenize(line: &str) -> Result<Vec<Spanned>, ParseError> {
+    let mut tokens = Vec::new();
     let mut current = String::new();
-    let mut in_quotes = None;
-    let mut brace_depth = 0;
-    let mut chars = command.trim().chars().peekable();
+    let mut start = 0;
+    let mut brace_depth: usize = 0;
+    let mut chars = line.char_indices().peekable();
+    // index of the delimiter token of every `<<` waiting for its body
+    let mut here_docs: Vec<(usize, bool)> = Vec::new();
 
-    while let Some(ch) = chars.next() {
+    while let Some((i, ch)) = chars.next() {
+        if current.is_empty() {
+            start = i;
+        }
         match ch {
-            '#' if in_quotes.is_none() => break,
-            '\'' | '"' if in_quotes.is_none() => in_quotes = Some(ch),
-            q if Some(q) == in_quotes => in_quotes = None,
-            '{' if in_quotes.is_none() => {
+            '\'' => {
+                current.push(ch);
+                loop {
+                    match chars.next() {
+                        Some((_, '\'')) => {
+                            current.push('\'');
+                            break;
+                        }
+                        Some((_, c)) => current.push(c),
+                        None => return Err(ParseError::UnclosedQuote('\'')),
+                    }
+                }
+            }
+            '"' => {
+                current.push(ch);
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => {
+                            current.push('"');
+                            break;
+                        }
+                        Some((_, '\\')) => {
+                            current.push('\\');
+                            match chars.next() {
+                                Some((_, c)) => current.push(c),
+                                None => return Err(ParseError::UnclosedQuote('"')),
+                            }
+                        }
+                        Some((_, c)) => current.push(c),
+                        None => return Err(ParseError::UnclosedQuote('"')),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some((_, '\n')) => {}
+                Some((_, c)) => {
+                    current.push('\\');
+                    current.push(c);
+                }
+                None => return Err(ParseError::Incomplete),
+            },
+            '#' if current.is_empty() => while chars.next_if(|&(_, c)| c != '\n').is_some() {},
+            // `{ cmd; }` is a group, `{a,b}` and `{a b}` are brace expansion
+            '{' if current.is_empty()
+                && chars
+                    .peek()
+                    .is_none_or(|&(_, c)| matches!(c, ' ' | '\t' | '\n')) =>
+            {
+                current.push(ch);
+                push_word(&mut tokens, &mut current, i, i + 1);
+            }
+            '{' => {
                 brace_depth += 1;
                 current.push(ch);
             }
-            '}' if in_quotes.is_none() => {
-                brace_depth = (brace_depth as i32 - 1).max(0) as usize;
+            '}' => {
+                brace_depth = brace_depth.saturating_sub(1);
                 current.push(ch);
             }
-
-            ' ' | '\t' | '|' | '>' if in_quotes.is_none() && brace_depth == 0 => {
-                if !current.is_empty() {
-                    args.extend(expand_braces(&current));
+            ' ' | '\t' if brace_depth > 0 => current.push(ch),
+            ' ' | '\t' => push_word(&mut tokens, &mut current, start, i),
+            '\n' | ';' | '&' | '|' | '>' | '<' | '(' | ')' => {
+                let mut fd = if ch == '<' { 0 } else { 1 };
+                // in `>&1>file` the `1` is the target of `>&`, not a descriptor
+                let after_redirect = matches!(
+                    tokens.last(),
+                    Some(Spanned {
+                        token: Token::Redirect { .. },
+                        ..
+                    })
+                );
+                if matches!(ch, '>' | '<')
+                    && !after_redirect
+                    && !current.is_empty()
+                    && current.chars().all(|c| c.is_ascii_digit())
+                {
+                    fd = current.parse().unwrap_or(u32::MAX);
                     current.clear();
+                } else {
+                    push_word(&mut tokens, &mut current, start, i);
+                    start = i;
                 }
-
-                if ch == '|' || ch == '>' {
-                    let mut op = ch.to_string();
-                    if let Some(&next) = chars.peek()
-                        && ch == '>'
-                        && (next == '>' || next == '=')
-                    {
-                        op.push(chars.next().unwrap());
+                brace_depth = 0;
+                let token = match ch {
+                    '\n' => Token::Newline,
+                    ';' if chars.next_if(|&(_, c)| c == ';').is_some() => Token::DoubleSemi,
+                    ';' => Token::Semi,
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    '&' if chars.next_if(|&(_, c)| c == '&').is_some() => Token::And,
+                    '&' if chars.next_if(|&(_, c)| c == '>').is_some() => Token::Redirect {
+                        fd,
+                        op: RedirectOp::Both {
+                            append: chars.next_if(|&(_, c)| c == '>').is_some(),
+                        },
+                    },
+                    '&' => Token::Amp,
+                    '|' if chars.next_if(|&(_, c)| c == '|').is_some() => Token::Or,
+                    '|' => Token::Pipe,
+                    '>' => {
+                        let op = match chars.next_if(|&(_, c)| matches!(c, '>' | '&' | '|')) {
+                            Some((_, '>')) => RedirectOp::Append,
+                            Some((_, '&')) => RedirectOp::Duplicate,
+                            Some(_) => RedirectOp::Clobber,
+                            None => RedirectOp::Write,
+                        };
+                        Token::Redirect { fd, op }
                     }
-                    args.push(op);
+                    _ => {
+                        let op = match (
+                            chars.next_if(|&(_, c)| c == '<').is_some(),
+                            chars.next_if(|&(_, c)| c == '<').is_some(),
+                        ) {
+                            (false, _) => RedirectOp::Read,
+                            (true, true) => RedirectOp::HereString,
+                            (true, false) => {
+                                let strip_tabs = chars.next_if(|&(_, c)| c == '-').is_some();
+                                here_docs.push((tokens.len() + 1, strip_tabs));
+                                RedirectOp::HereDoc { expand: true }
+                            }
+                        };
+                        Token::Redirect { fd, op }
+                    }
+                };
+                let end = chars.peek().map_or(line.len(), |&(i, _)| i);
+                tokens.push(Spanned { token, start, end });
+                if ch == '\n' {
+                    read_here_docs(&mut tokens, &mut here_docs, &mut chars)?;
                 }
             }
-
             _ => current.push(ch),
         }
     }
+    push_word(&mut tokens, &mut current, start, line.len());
+    read_here_docs(&mut tokens, &mut here_docs, &mut chars)?;
+    Ok(tokens)
+}
 
-    if !current.is_empty() {
-        args.extend(expand_braces(&current));
+/// Fills in the bodies of the here-documents started on the line that has
+/// just ended.
+fn read_here_docs(
+    tokens: &mut [Spanned],
+    here_docs: &mut Vec<(usize, bool)>,
+    chars: &mut Peekable<CharIndices>,
+) -> Result<(), ParseError> {
+    for (index, strip_tabs) in here_docs.drain(..) {
+        // `cat <<` without a delimiter is left to the parser to report
+        let Some(Token::Word(word)) = tokens.get(index).map(|spanned| &spanned.token) else {
+            continue;
+        };
+        let (delimiter, expand) = here_delimiter(word);
+        let body = here_body(chars, &delimiter, strip_tabs)?;
+        if let Token::Redirect { op, .. } = &mut tokens[index - 1].token {
+            *op = RedirectOp::HereDoc { expand };
+        }
+        tokens[index].token = Token::Word(body);
     }
+    Ok(())
+}
+
+/// Words that close or continue a compound command; they can't start one.
+pub const CLOSERS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// Words that start a compound command.
+pub const OPENERS: [&str; 6] = ["{", "if", "while", "until", "for", "case"];
 
-    args
+/// Alias name to the text it stands for.
+pub type Aliases = HashMap<String, String>;
+
+struct Parser<'t> {
+    line: &'t str,
+    tokens: Vec<Spanned>,
+    pos: usize,
+    aliases: &'t Aliases,
+    /// Aliases being expanded, with the token their text ends at: inside
+    /// its own text an alias is an ordinary word (`alias ls='ls -F'`).
+    expanding: Vec<(String, usize)>,
 }
 
-fn expand_braces(input: &str) -> Vec<String> {
-    if let Some(start) = input.find('{') {
-        let mut depth = 0;
-        let mut end = None;
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|spanned| &spanned.token)
+    }
 
-        for (i, ch) in input.char_indices().skip(start) {
-            if ch == '{' {
-                depth += 1;
-            } else if ch == '}' {
-                depth -= 1;
-                if depth == 0 {
-                    end = Some(i);
-                    break;
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(token) => ParseError::UnexpectedToken(token.to_string()),
+            None => ParseError::Incomplete,
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    /// The next token is the unquoted word `keyword`.
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word == keyword)
+    }
+
+    fn next_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.at_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        self.skip_newlines();
+        if self.next_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    /// A list that ends before one of the `ends` keywords (or `;;`) or at
+    /// the end of the input.
+    fn list(&mut self, ends: &[&str]) -> Result<List, ParseError> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                None => break,
+                Some(Token::DoubleSemi) if ends.contains(&";;") => break,
+                Some(Token::Word(word)) if ends.contains(&word.as_str()) => break,
+                _ => {}
+            }
+            let start = self.tokens[self.pos].start;
+            let and_or = self.and_or()?;
+            let end = self.tokens[self.pos - 1].end;
+            let background = self.peek() == Some(&Token::Amp);
+            match self.peek() {
+                Some(Token::Amp | Token::Semi | Token::Newline) => self.pos += 1,
+                Some(Token::DoubleSemi) if ends.contains(&";;") => {}
+                None => {}
+                Some(_) => return Err(self.unexpected()),
+            }
+            items.push(ListItem {
+                and_or,
+                background,
+                text: self.line[start..end].to_string(),
+            });
+        }
+        Ok(items)
+    }
+
+    /// Like `list`, but at least one command is needed: `if then` is an
+    /// error, not an empty condition.
+    fn body(&mut self, ends: &[&str]) -> Result<List, ParseError> {
+        let list = self.list(ends)?;
+        if list.is_empty() {
+            Err(self.unexpected())
+        } else {
+            Ok(list)
+        }
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.peek() {
+                Some(Token::And) => Connector::And,
+                Some(Token::Or) => Connector::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            rest.push((connector, self.pipeline()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let start = self.tokens.get(self.pos).map_or(0, |token| token.start);
+        let mut commands = vec![self.command()?];
+        while self.peek() == Some(&Token::Pipe) {
+            self.pos += 1;
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+        let end = self.tokens[self.pos - 1].end;
+        Ok(Pipeline {
+            commands,
+            text: self.line[start..end].to_string(),
+        })
+    }
+
+    /// Replaces the word at `pos` with the tokens of its alias, if it has
+    /// one that isn't already being expanded here.
+    fn expand_alias(&mut self) {
+        loop {
+            let pos = self.pos;
+            self.expanding.retain(|(_, end)| *end > pos);
+            let Some(Token::Word(word)) = self.peek() else {
+                return;
+            };
+            if self.expanding.iter().any(|(name, _)| name == word) {
+                return;
+            }
+            let Some(text) = self.aliases.get(word) else {
+                return;
+            };
+            let Ok(tokens) = tokenize(text) else {
+                return;
+            };
+            // the alias text has no place in the line, errors and job texts
+            // point at the alias name
+            let (start, end) = (self.tokens[pos].start, self.tokens[pos].end);
+            let tokens: Vec<_> = tokens
+                .into_iter()
+                .map(|spanned| Spanned {
+                    token: spanned.token,
+                    start,
+                    end,
+                })
+                .collect();
+            let len = tokens.len();
+            let name = word.clone();
+            self.tokens.splice(pos..pos + 1, tokens);
+            self.expanding
+                .iter_mut()
+                .for_each(|(_, end)| *end = *end + len - 1);
+            self.expanding.push((name, pos + len));
+            if len == 0 {
+                return;
+            }
+        }
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        self.expand_alias();
+        let keyword = match self.peek() {
+            Some(Token::Word(word)) => word.clone(),
+            _ => String::new(),
+        };
+        let is_function = matches!(
+            (self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2)),
+            (
+                Some(Spanned {
+                    token: Token::LParen,
+                    ..
+                }),
+                Some(Spanned {
+                    token: Token::RParen,
+                    ..
+                })
+            )
+        );
+        if is_function && is_name(&keyword) {
+            self.pos += 3;
+            return self.function(keyword);
+        }
+        let compound = match keyword.as_str() {
+            "function" => {
+                self.pos += 1;
+                let name = match self.peek() {
+                    Some(Token::Word(name)) if is_name(name) => name.clone(),
+                    _ => return Err(self.unexpected()),
+                };
+                self.pos += 1;
+                if self.peek() == Some(&Token::LParen) {
+                    self.pos += 1;
+                    if self.peek() != Some(&Token::RParen) {
+                        return Err(self.unexpected());
+                    }
+                    self.pos += 1;
                 }
+                return self.function(name);
+            }
+            "{" => {
+                self.pos += 1;
+                let body = self.body(&["}"])?;
+                self.keyword("}")?;
+                Compound::Group(body)
             }
+            "if" => self.if_clause()?,
+            "while" | "until" => self.loop_clause()?,
+            "for" => self.for_clause()?,
+            "case" => self.case_clause()?,
+            keyword if CLOSERS.contains(&keyword) => return Err(self.unexpected()),
+            _ => return Ok(Command::Simple(self.simple()?)),
+        };
+        let mut redirects = Vec::new();
+        while let Some(&Token::Redirect { fd, op }) = self.peek() {
+            redirects.push(self.redirect(fd, op)?);
         }
+        Ok(Command::Compound(compound, redirects))
+    }
 
-        if let Some(end_idx) = end {
-            let prefix = &input[..start];
-            let suffix = &input[end_idx + 1..];
-            let content = &input[start + 1..end_idx];
+    /// The body of `name() ...` or `function name ...`.
+    fn function(&mut self, name: String) -> Result<Command, ParseError> {
+        self.skip_newlines();
+        match self.peek() {
+            Some(Token::Word(word)) if OPENERS.contains(&word.as_str()) => Ok(Command::Function {
+                name,
+                body: Box::new(self.command()?),
+            }),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn if_clause(&mut self) -> Result<Compound, ParseError> {
+        self.pos += 1;
+        let mut branches = Vec::new();
+        loop {
+            let condition = self.body(&["then"])?;
+            self.keyword("then")?;
+            let body = self.body(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+            if !self.next_keyword("elif") {
+                break;
+            }
+        }
+        let otherwise = if self.next_keyword("else") {
+            Some(self.body(&["fi"])?)
+        } else {
+            None
+        };
+        self.keyword("fi")?;
+        Ok(Compound::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn loop_clause(&mut self) -> Result<Compound, ParseError> {
+        let until = self.at_keyword("until");
+        self.pos += 1;
+        let condition = self.body(&["do"])?;
+        self.keyword("do")?;
+        let body = self.body(&["done"])?;
+        self.keyword("done")?;
+        Ok(Compound::Loop {
+            until,
+            condition,
+            body,
+        })
+    }
 
-            let parts = split_brace_content(content);
-            let mut result = Vec::new();
+    fn for_clause(&mut self) -> Result<Compound, ParseError> {
+        self.pos += 1;
+        let name = match self.peek() {
+            Some(Token::Word(name)) if is_name(name) => name.clone(),
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        self.skip_newlines();
+        let words = if self.next_keyword("in") {
+            let mut words = Vec::new();
+            while let Some(Token::Word(word)) = self.peek() {
+                words.push(word.clone());
+                self.pos += 1;
+            }
+            match self.peek() {
+                Some(Token::Semi | Token::Newline) => self.pos += 1,
+                _ => return Err(self.unexpected()),
+            }
+            Some(words)
+        } else {
+            if self.peek() == Some(&Token::Semi) {
+                self.pos += 1;
+            }
+            None
+        };
+        self.keyword("do")?;
+        let body = self.body(&["done"])?;
+        self.keyword("done")?;
+        Ok(Compound::For { name, words, body })
+    }
 
-            for part in parts {
-                let full_word = format!("{}{}{}", prefix, part, suffix);
-                result.extend(expand_braces(&full_word));
+    fn case_clause(&mut self) -> Result<Compound, ParseError> {
+        self.pos += 1;
+        let word = match self.peek() {
+            Some(Token::Word(word)) => word.clone(),
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        self.keyword("in")?;
+        let mut arms = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.next_keyword("esac") {
+                break;
+            }
+            if self.peek() == Some(&Token::LParen) {
+                self.pos += 1;
+            }
+            let mut patterns = Vec::new();
+            loop {
+                let Some(Token::Word(pattern)) = self.peek() else {
+                    return Err(self.unexpected());
+                };
+                patterns.push(pattern.clone());
+                self.pos += 1;
+                if self.peek() != Some(&Token::Pipe) {
+                    break;
+                }
+                self.pos += 1;
+            }
+            if self.peek() != Some(&Token::RParen) {
+                return Err(self.unexpected());
+            }
+            self.pos += 1;
+            let body = self.list(&["esac", ";;"])?;
+            arms.push(CaseArm { patterns, body });
+            if self.peek() == Some(&Token::DoubleSemi) {
+                self.pos += 1;
             }
-            return result;
         }
+        Ok(Compound::Case { word, arms })
     }
-    vec![input.to_string()]
-}
 
-fn split_brace_content(content: &str) -> Vec<String> {
-    let mut parts = Vec::new();
-    let mut current = String::new();
-    let mut depth = 0;
+    /// The target of the redirection token at `pos`.
+    fn redirect(&mut self, fd: u32, op: RedirectOp) -> Result<Redirect, ParseError> {
+        self.pos += 1;
+        let Some(Token::Word(target)) = self.peek() else {
+            return Err(match self.peek() {
+                Some(_) => self.unexpected(),
+                None => ParseError::UnexpectedToken("newline".to_string()),
+            });
+        };
+        let redirect = Redirect {
+            fd,
+            op,
+            target: target.clone(),
+        };
+        self.pos += 1;
+        Ok(redirect)
+    }
 
-    for ch in content.chars() {
-        if (ch == ',' || ch == ' ') && depth == 0 {
-            if !current.is_empty() {
-                parts.push(current.clone());
-                current.clear();
+    fn simple(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut assignments = Vec::new();
+        let mut words = Vec::new();
+        let mut redirects = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::Word(word))
+                    if words.is_empty()
+                        && let Some((name, value)) = assignment(word) =>
+                {
+                    assignments.push((name.to_string(), value.to_string()));
+                    self.pos += 1;
+                    self.expand_alias();
+                }
+                Some(Token::Word(word)) => {
+                    words.push(word.clone());
+                    self.pos += 1;
+                }
+                Some(&Token::Redirect { fd, op }) => redirects.push(self.redirect(fd, op)?),
+                _ => break,
             }
+        }
+        if assignments.is_empty() && words.is_empty() && redirects.is_empty() {
+            Err(self.unexpected())
         } else {
-            if ch == '{' {
-                depth += 1;
+            Ok(SimpleCommand {
+                assignments,
+                words,
+                redirects,
+            })
+        }
+    }
+}
+
+/// Parses a whole input line (or script) into a list of and-or chains:
+/// `a && b || c ; d | e & f`, with `if`, loops and `case` as commands.
+pub fn parse_line(line: &str) -> Result<List, ParseError> {
+    parse_with_aliases(line, &Aliases::new())
+}
+
+/// `parse_line` with the first word of every simple command replaced by
+/// its alias.
+pub fn parse_with_aliases(line: &str, aliases: &Aliases) -> Result<List, ParseError> {
+    let mut parser = Parser {
+        line,
+        tokens: tokenize(line)?,
+        pos: 0,
+        aliases,
+        expanding: Vec::new(),
+    };
+    let list = parser.list(&[])?;
+    match parser.peek() {
+        Some(_) => Err(parser.unexpected()),
+        None => Ok(list),
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "{}", word),
+            Self::Redirect { fd, op } => {
+                let both = matches!(op, RedirectOp::Both { .. });
+                if !both && *fd != if op.is_input() { 0 } else { 1 } {
+                    write!(f, "{}", fd)?;
+                }
+                let op = match op {
+                    RedirectOp::Write => ">",
+                    RedirectOp::Clobber => ">|",
+                    RedirectOp::Append => ">>",
+                    RedirectOp::Duplicate => ">&",
+                    RedirectOp::Both { append: false } => "&>",
+                    RedirectOp::Both { append: true } => "&>>",
+                    RedirectOp::Read => "<",
+                    RedirectOp::HereDoc { .. } => "<<",
+                    RedirectOp::HereString => "<<<",
+                };
+                write!(f, "{}", op)
+            }
+            Self::Pipe => write!(f, "|"),
+            Self::And => write!(f, "&&"),
+            Self::Or => write!(f, "||"),
+            Self::Semi => write!(f, ";"),
+            Self::DoubleSemi => write!(f, ";;"),
+            Self::LParen => write!(f, "("),
+            Self::RParen => write!(f, ")"),
+            Self::Amp => write!(f, "&"),
+            Self::Newline => write!(f, "newline"),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedToken(token) => {
+                write!(f, "shu: syntax error near unexpected token `{}'", token)
+            }
+            Self::UnclosedQuote(quote) => {
+                write!(
+                    f,
+                    "shu: unexpected EOF while looking for matching `{}'",
+                    quote
+                )
             }
-            if ch == '}' {
-                depth -= 1;
+            Self::UnclosedHereDoc(delimiter) => {
+                write!(
+                    f,
+                    "shu: here-document wanted `{}' but the input ended",
+                    delimiter
+                )
             }
-            current.push(ch);
+            Self::Incomplete => write!(f, "shu: syntax error: unexpected end of input"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_build::ast::{AndOr, Command, Compound, Connector, Pipeline};
+
+    fn parsed(line: &str) -> List {
+        match parse_line(line) {
+            Ok(list) => list,
+            Err(e) => panic!("{}: {}", line, e),
+        }
+    }
+
+    fn error(line: &str) -> String {
+        match parse_line(line) {
+            Ok(_) => panic!("{} parsed", line),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    /// The words of every simple command of a pipeline.
+    fn words(pipeline: &Pipeline) -> Vec<Vec<&str>> {
+        pipeline
+            .commands
+            .iter()
+            .map(|command| match command {
+                Command::Simple(simple) => simple.words.iter().map(String::as_str).collect(),
+                _ => panic!("not a simple command"),
+            })
+            .collect()
+    }
+
+    fn connectors(and_or: &AndOr) -> Vec<Connector> {
+        and_or
+            .rest
+            .iter()
+            .map(|(connector, _)| *connector)
+            .collect()
+    }
+
+    #[test]
+    fn lists_and_and_or_chains() {
+        let list = parsed("a 1 && b || c; d | e -x & f\ng");
+        assert_eq!(list.len(), 4);
+        assert_eq!(words(&list[0].and_or.first), [["a", "1"]]);
+        assert!(connectors(&list[0].and_or) == [Connector::And, Connector::Or]);
+        assert_eq!(words(&list[0].and_or.rest[1].1), [["c"]]);
+        assert_eq!(list[0].text, "a 1 && b || c");
+        assert!(!list[0].background);
+        assert_eq!(words(&list[1].and_or.first), [vec!["d"], vec!["e", "-x"]]);
+        assert!(list[1].background);
+        assert_eq!(list[1].text, "d | e -x");
+        assert_eq!(words(&list[3].and_or.first), [["g"]]);
+    }
+
+    #[test]
+    fn quotes_stay_in_words() {
+        let list = parsed("echo 'a b' \"c;d\" e\\ f");
+        assert_eq!(
+            words(&list[0].and_or.first),
+            [["echo", "'a b'", "\"c;d\"", "e\\ f"]]
+        );
+    }
+
+    /// The redirections of the first command of a line.
+    fn redirects(line: &str) -> Vec<(u32, RedirectOp, String)> {
+        match &parsed(line)[0].and_or.first.commands[0] {
+            Command::Simple(simple) => simple
+                .redirects
+                .iter()
+                .map(|redirect| (redirect.fd, redirect.op, redirect.target.clone()))
+                .collect(),
+            _ => panic!("not a simple command"),
+        }
+    }
+
+    #[test]
+    fn input_redirections_and_here_documents() {
+        assert!(redirects("cat < in") == [(0, RedirectOp::Read, "in".to_string())]);
+        assert!(redirects("cat <<< 'a b'") == [(0, RedirectOp::HereString, "'a b'".to_string())]);
+        let body = "one $X\n\ttwo\n";
+        assert!(
+            redirects("cat << EOF\none $X\n\ttwo\nEOF")
+                == [(0, RedirectOp::HereDoc { expand: true }, body.to_string())]
+        );
+        assert!(
+            redirects("cat <<- 'EOF'\n\tone\n\tEOF")
+                == [(
+                    0,
+                    RedirectOp::HereDoc { expand: false },
+                    "one\n".to_string()
+                )]
+        );
+        assert!(parse_line("cat << EOF\nnever closed").is_err_and(|e| e.is_incomplete()));
+    }
+
+    #[test]
+    fn output_redirections() {
+        assert!(
+            redirects("a > out 2>&1 >> log >| f &> both &>> all")
+                == [
+                    (1, RedirectOp::Write, "out".to_string()),
+                    (2, RedirectOp::Duplicate, "1".to_string()),
+                    (1, RedirectOp::Append, "log".to_string()),
+                    (1, RedirectOp::Clobber, "f".to_string()),
+                    (1, RedirectOp::Both { append: false }, "both".to_string()),
+                    (1, RedirectOp::Both { append: true }, "all".to_string()),
+                ]
+        );
+    }
+
+    fn compound(line: &str) -> Compound {
+        match &parsed(line)[0].and_or.first.commands[0] {
+            Command::Compound(compound, _) => compound.clone(),
+            _ => panic!("{}: not a compound command", line),
         }
     }
-    if !current.is_empty() {
-        parts.push(current);
+
+    /// The words of the first command of each item of a list.
+    fn first_words(list: &List) -> Vec<Vec<&str>> {
+        list.iter()
+            .flat_map(|item| words(&item.and_or.first))
+            .collect()
+    }
+
+    #[test]
+    fn if_and_loops() {
+        let Compound::If {
+            branches,
+            otherwise,
+        } = compound("if a; then b; elif c\nthen d; e; else f; fi")
+        else {
+            panic!("not an if");
+        };
+        assert_eq!(branches.len(), 2);
+        assert_eq!(first_words(&branches[1].0), [["c"]]);
+        assert_eq!(first_words(&branches[1].1), [["d"], ["e"]]);
+        assert_eq!(first_words(&otherwise.unwrap()), [["f"]]);
+
+        let Compound::Loop { until, body, .. } = compound("until a; do b; done") else {
+            panic!("not a loop");
+        };
+        assert!(until);
+        assert_eq!(first_words(&body), [["b"]]);
+
+        let Compound::For { name, words, .. } = compound("for x in 1 \"2 3\"; do :; done") else {
+            panic!("not a for");
+        };
+        assert_eq!(name, "x");
+        assert_eq!(words.unwrap(), ["1", "\"2 3\""]);
+        assert!(matches!(
+            compound("for x; do :; done"),
+            Compound::For { words: None, .. }
+        ));
+    }
+
+    #[test]
+    fn case_arms() {
+        let Compound::Case { word, arms } =
+            compound("case $x in a|b*) one;; (c) two; three;; *) ;; esac")
+        else {
+            panic!("not a case");
+        };
+        assert_eq!(word, "$x");
+        assert_eq!(arms.len(), 3);
+        assert_eq!(arms[0].patterns, ["a", "b*"]);
+        assert_eq!(first_words(&arms[1].body), [["two"], ["three"]]);
+        assert!(arms[2].body.is_empty());
+    }
+
+    #[test]
+    fn compound_commands_redirect_and_nest() {
+        let list = parsed("while read l; do if x; then break; fi; done < f | cat");
+        let Command::Compound(Compound::Loop { body, .. }, redirects) =
+            &list[0].and_or.first.commands[0]
+        else {
+            panic!("not a loop");
+        };
+        assert_eq!(redirects[0].target, "f");
+        assert!(matches!(
+            body[0].and_or.first.commands[0],
+            Command::Compound(Compound::If { .. }, _)
+        ));
+        assert_eq!(list[0].and_or.first.commands.len(), 2);
+        assert!(parse_line("if a; then b").is_err_and(|e| e.is_incomplete()));
+        assert!(parse_line("while a; done").is_err());
+        assert_eq!(
+            words(&parsed("echo if then")[0].and_or.first),
+            [["echo", "if", "then"]]
+        );
+    }
+
+    #[test]
+    fn function_definitions() {
+        for line in [
+            "f() { a; b; }",
+            "function f { a; b; }",
+            "f ()\n{\n a\n b\n}",
+        ] {
+            let list = parsed(line);
+            let Command::Function { name, body } = &list[0].and_or.first.commands[0] else {
+                panic!("{}: not a function", line);
+            };
+            assert_eq!(name, "f");
+            let Command::Compound(Compound::Group(body), _) = &**body else {
+                panic!("{}: body is not a group", line);
+            };
+            assert_eq!(first_words(body), [["a"], ["b"]]);
+        }
+        assert!(matches!(
+            parsed("f() if a; then b; fi")[0].and_or.first.commands[0],
+            Command::Function { .. }
+        ));
+        assert!(parse_line("f() a").is_err());
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            error("a && && b"),
+            "shu: syntax error near unexpected token `&&'"
+        );
+        assert_eq!(error("| a"), "shu: syntax error near unexpected token `|'");
+        assert!(parse_line("a &&").is_err_and(|e| e.is_incomplete()));
+        assert!(parse_line("echo 'a").is_err_and(|e| e.is_incomplete()));
     }
-    parts
 }
@@ -0,0 +1,110 @@
+use super::{
+    ast::{Redirect, RedirectOp},
+    build::BuildError,
+};
+use std::{
+    fs::File,
+    io::{self, PipeReader, PipeWriter, Read, Write},
+    path::Path,
+    process::Stdio,
+    thread,
+};
+
+/// What a command reads as its standard input when it is not the terminal.
+/// Builtins read it through `InputFile::Pipe`, external programs get the
+/// file descriptor itself.
+pub enum InputStream {
+    Pipe(PipeReader),
+    File(File),
+}
+
+/// The ends of the pipes a single pipeline stage is connected to.
+pub struct Plumbing {
+    pub stdin: Option<InputStream>,
+    pub stdout: Option<PipeWriter>,
+}
+
+impl InputStream {
+    pub fn stdio(&self) -> io::Result<Stdio> {
+        match self {
+            Self::Pipe(pipe) => Ok(Stdio::from(pipe.try_clone()?)),
+            Self::File(file) => Ok(Stdio::from(file.try_clone()?)),
+        }
+    }
+
+    /// A pipe with `text` written into it from another thread, so a long
+    /// here-document can't fill the pipe before anyone reads it.
+    fn text<'a>(text: String) -> Result<Self, BuildError<'a>> {
+        let (reader, mut writer) = io::pipe().map_err(BuildError::PipeError)?;
+        thread::spawn(move || {
+            // the command may stop reading early, that's not an error
+            let _ = writer.write_all(text.as_bytes());
+        });
+        Ok(Self::Pipe(reader))
+    }
+
+    /// The stream `<`, `<<` or `<<<` gives the command (the last one wins),
+    /// if it has any of them. Targets have to be expanded already: the
+    /// body for here-documents and here-strings.
+    pub fn redirected<'a>(
+        redirects: &[Redirect],
+        path: &Path,
+    ) -> Result<Option<Self>, BuildError<'a>> {
+        let mut stream = None;
+        for redirect in redirects.iter().filter(|redirect| redirect.op.is_input()) {
+            if redirect.fd != 0 {
+                return Err(BuildError::BadDescriptor(redirect.fd));
+            }
+            stream = Some(match redirect.op {
+                RedirectOp::Read => {
+                    let path = path.join(&redirect.target);
+                    match File::open(&path) {
+                        Ok(file) => Self::File(file),
+                        Err(e) => return Err(BuildError::UnopenedFile(path, e)),
+                    }
+                }
+                _ => Self::text(redirect.target.clone())?,
+            });
+        }
+        Ok(stream)
+    }
+}
+
+impl Read for &InputStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            InputStream::Pipe(pipe) => {
+                let mut pipe: &PipeReader = pipe;
+                pipe.read(buf)
+            }
+            InputStream::File(file) => {
+                let mut file: &File = file;
+                file.read(buf)
+            }
+        }
+    }
+}
+
+impl Plumbing {
+    /// Connects `count` stages: the stdout of every stage is the stdin of
+    /// the next one, the first stage reads and the last one writes wherever
+    /// the shell does.
+    pub fn connect<'a>(count: usize) -> Result<Vec<Self>, BuildError<'a>> {
+        let mut stages = Vec::with_capacity(count);
+        let mut stdin = None;
+        for i in 0..count {
+            let (next_stdin, stdout) = if i + 1 < count {
+                let (reader, writer) = io::pipe().map_err(BuildError::PipeError)?;
+                (Some(InputStream::Pipe(reader)), Some(writer))
+            } else {
+                (None, None)
+            };
+            stages.push(Self {
+                stdin: stdin.take(),
+                stdout,
+            });
+            stdin = next_stdin;
+        }
+        Ok(stages)
+    }
+}
@@ -1,13 +1,14 @@
 use std::{
     fmt,
-    io::{self, BufRead, BufReader, PipeReader, Read, Write},
+    io::{self, BufRead, BufReader, Read, Write},
     path::Path,
 };
 
 use crate::command_build::{
     build::{BuildError, CommandBuild},
-    command::{Command, CommandError},
+    command::{Command, CommandError, ErrorStatus, FAILURE, SUCCESS, Status, cancelled},
     parse::{CommandBackPack, InputFile},
+    plumbing::InputStream,
 };
 
 pub struct Cat<'a> {
@@ -27,7 +28,11 @@ impl<'a> Cat<'a> {
         index: &mut usize,
     ) -> io::Result<bool> {
         let buffer = BufReader::new(file);
-        for line in buffer.lines().map_while(Result::ok) {
+        for line in buffer
+            .lines()
+            .map_while(Result::ok)
+            .take_while(|_| !cancelled())
+        {
             if self.squize_blank && line.trim().is_empty() {
                 if last_blank {
                     continue;
@@ -54,10 +59,30 @@ impl<'a> Cat<'a> {
 }
 
 impl<'a> CommandBuild<'a, CatError> for Cat<'a> {
+    const FLAGS: &'static [&'static str] = &[
+        "-in",
+        "--input-file",
+        "-f",
+        "--from",
+        "-he",
+        "--help",
+        "--help-mode",
+        "-n",
+        "-ln",
+        "--line-number",
+        "-E",
+        "--show-ends",
+        "--show",
+        "-b",
+        "--non-blank",
+        "-s",
+        "--squeze",
+    ];
+
     fn new_obj(
         args: Vec<&'a str>,
         path: &'a Path,
-        pipe: Option<&'a PipeReader>,
+        pipe: Option<&'a InputStream>,
     ) -> Result<Box<dyn Command<'a, CatError> + 'a>, CommandError<'a, CatError>> {
         let mut i = 0;
         let mut input_files: Vec<InputFile> = Vec::new();
@@ -71,7 +96,10 @@ impl<'a> CommandBuild<'a, CatError> for Cat<'a> {
         while i < args.len() {
             if args[i].starts_with('-') || args[i].starts_with('>') {
                 match args[i].trim() {
-                    "-" => input_files.push(InputFile::Stdin),
+                    "-" => input_files.push(match pipe {
+                        Some(pipe) => InputFile::Pipe(pipe),
+                        None => InputFile::Stdin,
+                    }),
                     "-in" | "--input-file" | "-f" | "--from" => {
                         match CommandBackPack::get_next(&args, i) {
                             Ok(res) => {
@@ -113,8 +141,8 @@ impl<'a> Command<'a, CatError> for Cat<'a> {
     fn run(
         mut self: Box<Self>,
         output: &mut CommandBackPack,
-    ) -> Result<bool, CommandError<'a, CatError>> {
-        let mut exit_code = true;
+    ) -> Result<Status, CommandError<'a, CatError>> {
+        let mut exit_code = SUCCESS;
         let mut last_blank = false;
         if self.input_files.is_empty() {
             self.input_files.push(InputFile::Stdin);
@@ -124,7 +152,7 @@ impl<'a> Command<'a, CatError> for Cat<'a> {
             let file = match Self::input_type(file) {
                 Ok(file) => file,
                 Err(e) => {
-                    exit_code = false;
+                    exit_code = FAILURE;
                     write!(output.stderr, "{}", e)?;
                     continue;
                 }
@@ -160,6 +188,8 @@ impl<'a> Command<'a, CatError> for Cat<'a> {
 
 pub enum CatError {}
 
+impl ErrorStatus for CatError {}
+
 impl fmt::Display for CatError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "unknown Error")
@@ -1,13 +1,14 @@
 use std::{
     fmt,
-    io::{self, BufRead, BufReader, PipeReader, Read, Write},
+    io::{self, BufRead, BufReader, Read, Write},
     path::Path,
 };
 
 use crate::command_build::{
     build::{BuildError, CommandBuild},
-    command::{Command, CommandError},
+    command::{Command, CommandError, ErrorStatus, FAILURE, SUCCESS, Status, USAGE, cancelled},
     parse::{CommandBackPack, InputFile},
+    plumbing::InputStream,
 };
 
 pub struct Grep<'a> {
@@ -19,10 +20,32 @@ pub struct Grep<'a> {
 }
 
 impl<'a> CommandBuild<'a, GrepError> for Grep<'a> {
+    const FLAGS: &'static [&'static str] = &[
+        "-in",
+        "--input-file",
+        "-f",
+        "--from",
+        "-p",
+        "--pattern",
+        "--pat",
+        "-c",
+        "--count",
+        "--count-lines",
+        "-he",
+        "--help",
+        "--help-mode",
+        "-n",
+        "-ln",
+        "--line-number",
+        "-i",
+        "--ignore-case",
+        "--ignore",
+    ];
+
     fn new_obj(
         args: Vec<&'a str>,
         path: &'a Path,
-        pipe: Option<&'a PipeReader>,
+        pipe: Option<&'a InputStream>,
     ) -> Result<Box<dyn Command<'a, GrepError> + 'a>, CommandError<'a, GrepError>> {
         let mut i = 0;
         let mut pattern: Option<&str> = None;
@@ -36,7 +59,10 @@ impl<'a> CommandBuild<'a, GrepError> for Grep<'a> {
         while i < args.len() {
             if args[i].starts_with('-') || args[i].starts_with('>') {
                 match args[i].trim() {
-                    "-" => input_files.push(InputFile::Stdin),
+                    "-" => input_files.push(match pipe {
+                        Some(pipe) => InputFile::Pipe(pipe),
+                        None => InputFile::Stdin,
+                    }),
                     "-in" | "--input-file" | "-f" | "--from" => {
                         match CommandBackPack::get_next(&args, i) {
                             Ok(res) => {
@@ -90,21 +116,30 @@ impl<'a> Grep<'a> {
             line.contains(pattern)
         }
     }
-    fn print_out(&self, output: &mut CommandBackPack, file: Box<dyn Read + 'a>) -> io::Result<()> {
+    fn print_out(
+        &self,
+        output: &mut CommandBackPack,
+        file: Box<dyn Read + 'a>,
+    ) -> io::Result<usize> {
         let buffer = BufReader::new(file);
+        let mut matched = 0;
         if self.count {
-            writeln!(
-                output.stdout,
-                "{}",
-                buffer
-                    .lines()
-                    .map_while(Result::ok)
-                    .filter(|line| Self::match_pattern(line, &self.pattern, self.ignore_case))
-                    .count()
-            )?;
+            matched = buffer
+                .lines()
+                .map_while(Result::ok)
+                .take_while(|_| !cancelled())
+                .filter(|line| Self::match_pattern(line, &self.pattern, self.ignore_case))
+                .count();
+            writeln!(output.stdout, "{}", matched)?;
         } else {
-            for (numero, line) in buffer.lines().map_while(Result::ok).enumerate() {
+            for (numero, line) in buffer
+                .lines()
+                .map_while(Result::ok)
+                .take_while(|_| !cancelled())
+                .enumerate()
+            {
                 if Self::match_pattern(&line, &self.pattern, self.ignore_case) {
+                    matched += 1;
                     let line = if self.line_number {
                         format!("{}. {}\n", numero + 1, line)
                     } else {
@@ -114,7 +149,7 @@ impl<'a> Grep<'a> {
                 }
             }
         }
-        Ok(())
+        Ok(matched)
     }
 }
 
@@ -122,8 +157,9 @@ impl<'a> Command<'a, GrepError> for Grep<'a> {
     fn run(
         mut self: Box<Self>,
         output: &mut CommandBackPack,
-    ) -> Result<bool, CommandError<'a, GrepError>> {
-        let mut exit_code = true;
+    ) -> Result<Status, CommandError<'a, GrepError>> {
+        let mut failed = false;
+        let mut matched = 0;
         if self.ignore_case {
             self.pattern = self.pattern.to_lowercase()
         }
@@ -131,14 +167,20 @@ impl<'a> Command<'a, GrepError> for Grep<'a> {
             let file = match Self::input_type(file) {
                 Ok(file) => file,
                 Err(e) => {
-                    exit_code = false;
+                    failed = true;
                     write!(output.stderr, "{}", e)?;
                     continue;
                 }
             };
-            Self::print_out(&self, output, file)?;
+            matched += Self::print_out(&self, output, file)?;
         }
-        Ok(exit_code)
+        Ok(if failed {
+            USAGE
+        } else if matched > 0 {
+            SUCCESS
+        } else {
+            FAILURE
+        })
     }
 
     fn help() {
@@ -167,6 +209,13 @@ pub enum GrepError {
     NoPattern,
 }
 
+/// grep exits with 2 on errors, 1 is kept for "nothing matched"
+impl ErrorStatus for GrepError {
+    fn status(&self) -> Status {
+        USAGE
+    }
+}
+
 impl fmt::Display for GrepError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
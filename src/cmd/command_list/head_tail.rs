@@ -1,7 +1,7 @@
 use std::{
     collections::VecDeque,
     fmt,
-    io::{BufRead, BufReader, PipeReader, Write},
+    io::{BufRead, BufReader, Write},
     path::Path,
 };
 
@@ -11,8 +11,9 @@ pub enum HeadTailError<'a> {
 
 use crate::command_build::{
     build::{BuildError, CommandBuild},
-    command::{Command, CommandError},
+    command::{Command, CommandError, ErrorStatus, FAILURE, SUCCESS, Status, cancelled},
     parse::{CommandBackPack, InputFile},
+    plumbing::InputStream,
 };
 
 pub struct HeadTail<'a> {
@@ -26,13 +27,13 @@ impl<'a> Command<'a, HeadTailError<'a>> for HeadTail<'a> {
     fn run(
         self: Box<Self>,
         output: &mut CommandBackPack,
-    ) -> Result<bool, CommandError<'a, HeadTailError<'a>>> {
-        let mut exit_code = false;
+    ) -> Result<Status, CommandError<'a, HeadTailError<'a>>> {
+        let mut exit_code = SUCCESS;
         for file in self.input_files.iter() {
             let reader = match Self::input_type(file) {
                 Ok(file) => BufReader::new(file),
                 Err(e) => {
-                    exit_code = false;
+                    exit_code = FAILURE;
                     write!(output.stderr, "{}", e)?;
                     continue;
                 }
@@ -41,6 +42,7 @@ impl<'a> Command<'a, HeadTailError<'a>> for HeadTail<'a> {
                 for line in reader
                     .lines()
                     .map_while(Result::ok)
+                    .take_while(|_| !cancelled())
                     .filter(|l| !(self.skip_empty && l.is_empty()))
                     .take(self.count)
                 {
@@ -48,7 +50,11 @@ impl<'a> Command<'a, HeadTailError<'a>> for HeadTail<'a> {
                 }
             } else {
                 let mut buffer = VecDeque::with_capacity(self.count);
-                for line in reader.lines().map_while(Result::ok) {
+                for line in reader
+                    .lines()
+                    .map_while(Result::ok)
+                    .take_while(|_| !cancelled())
+                {
                     if self.skip_empty && line.is_empty() {
                         continue;
                     }
@@ -92,10 +98,30 @@ impl<'a> Command<'a, HeadTailError<'a>> for HeadTail<'a> {
 }
 
 impl<'a> CommandBuild<'a, HeadTailError<'a>> for HeadTail<'a> {
+    const FLAGS: &'static [&'static str] = &[
+        "-i",
+        "--input-file",
+        "-f",
+        "--from",
+        "-c",
+        "--count",
+        "--count-lines",
+        "-t",
+        "--tail-mode",
+        "-h",
+        "--head-mode",
+        "-s",
+        "--skip-empty",
+        "--skip",
+        "-he",
+        "--help",
+        "--help-mode",
+    ];
+
     fn new_obj(
         args: Vec<&'a str>,
         path: &'a Path,
-        pipe: Option<&'a PipeReader>,
+        pipe: Option<&'a InputStream>,
     ) -> Result<Box<dyn Command<'a, HeadTailError<'a>> + 'a>, CommandError<'a, HeadTailError<'a>>>
     {
         let mut i = 0;
@@ -109,7 +135,10 @@ impl<'a> CommandBuild<'a, HeadTailError<'a>> for HeadTail<'a> {
         while i < args.len() {
             if args[i].starts_with('-') || args[i].starts_with('>') {
                 match args[i].trim() {
-                    "-" => input_files.push(InputFile::Stdin),
+                    "-" => input_files.push(match pipe {
+                        Some(pipe) => InputFile::Pipe(pipe),
+                        None => InputFile::Stdin,
+                    }),
                     "-i" | "--input-file" | "-f" | "--from" => {
                         if i + 1 >= args.len() {
                             return Err(CommandError::BuildError(BuildError::NoArgument(args[i])));
@@ -161,6 +190,8 @@ impl<'a> HeadTail<'a> {
     }
 }
 
+impl ErrorStatus for HeadTailError<'_> {}
+
 impl fmt::Display for HeadTailError<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -1,15 +1,16 @@
 use std::{
     fmt,
     fs::{self, DirEntry},
-    io::{self, PipeReader, Write},
+    io::{self, Write},
     os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
     path::{Path, PathBuf},
 };
 
 use crate::command_build::{
     build::{BuildError, CommandBuild},
-    command::{Command, CommandError},
-    parse::CommandBackPack,
+    command::{Command, CommandError, ErrorStatus, SUCCESS, Status},
+    parse::{CommandBackPack, OutputFile},
+    plumbing::InputStream,
 };
 
 pub struct Ls {
@@ -21,10 +22,24 @@ pub struct Ls {
 }
 
 impl<'a> CommandBuild<'a, LsError> for Ls {
+    const FLAGS: &'static [&'static str] = &[
+        "-he",
+        "--help",
+        "--help-mode",
+        "-F",
+        "--classify",
+        "-l",
+        "--long-format",
+        "-a",
+        "-all",
+        "-A",
+        "--almost-all",
+    ];
+
     fn new_obj(
         args: Vec<&'a str>,
         path: &'a Path,
-        _p: Option<&'a PipeReader>,
+        _p: Option<&'a InputStream>,
     ) -> Result<Box<dyn Command<'a, LsError> + 'a>, CommandError<'a, LsError>> {
         let mut i = 0;
         let mut dir: Option<PathBuf> = None;
@@ -68,7 +83,7 @@ impl<'a> Command<'a, LsError> for Ls {
     fn run(
         mut self: Box<Self>,
         output: &mut CommandBackPack,
-    ) -> Result<bool, CommandError<'a, LsError>> {
+    ) -> Result<Status, CommandError<'a, LsError>> {
         if self.show_hide && !self.show_hide_and {
             if self.full_info {
                 Self::print_info(".".into(), &mut output.stdout)?;
@@ -134,7 +149,7 @@ impl<'a> Command<'a, LsError> for Ls {
         } else {
             return Err(CommandError::Other("ls", LsError::NotDir(self.dire)));
         }
-        Ok(true)
+        Ok(SUCCESS)
     }
 
     fn help() {
@@ -160,8 +175,8 @@ impl<'a> Command<'a, LsError> for Ls {
     }
 }
 
-impl<'a> Ls {
-    fn print_info(path: PathBuf, outfile: &mut Box<dyn Write + 'a>) -> io::Result<()> {
+impl Ls {
+    fn print_info(path: PathBuf, outfile: &mut OutputFile) -> io::Result<()> {
         let metadata = match fs::metadata(&path) {
             Ok(m) => m,
             Err(e) => {
@@ -244,6 +259,8 @@ pub enum LsError {
     ReadDirError(PathBuf, io::Error),
 }
 
+impl ErrorStatus for LsError {}
+
 impl fmt::Display for LsError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
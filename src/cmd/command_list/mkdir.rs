@@ -1,13 +1,14 @@
 use std::{
     fmt, fs,
-    io::{self, PipeReader},
+    io::{self, Write},
     path::{Path, PathBuf},
 };
 
 use crate::command_build::{
     build::{BuildError, CommandBuild},
-    command::{Command, CommandError},
+    command::{Command, CommandError, ErrorStatus, SUCCESS, Status},
     parse::CommandBackPack,
+    plumbing::InputStream,
 };
 
 pub struct Mkdir<'a> {
@@ -18,10 +19,20 @@ pub struct Mkdir<'a> {
 }
 
 impl<'a> CommandBuild<'a, MkdirError> for Mkdir<'a> {
+    const FLAGS: &'static [&'static str] = &[
+        "-he",
+        "--help",
+        "--help-mode",
+        "-p",
+        "--parents",
+        "-v",
+        "--verbose",
+    ];
+
     fn new_obj(
         args: Vec<&'a str>,
         path: &'a Path,
-        _p: Option<&PipeReader>,
+        _p: Option<&InputStream>,
     ) -> Result<Box<dyn Command<'a, MkdirError> + 'a>, CommandError<'a, MkdirError>> {
         let mut i = 0;
         let mut format: Vec<&str> = Vec::new();
@@ -77,7 +88,7 @@ impl<'a> Command<'a, MkdirError> for Mkdir<'a> {
     fn run(
         self: Box<Self>,
         output: &mut CommandBackPack,
-    ) -> Result<bool, CommandError<'a, MkdirError>> {
+    ) -> Result<Status, CommandError<'a, MkdirError>> {
         for arg in self.command_format {
             if let Err(e) = Self::makedir(self.path, arg, self.parents) {
                 return Err(CommandError::Other("mkdir", e));
@@ -85,7 +96,7 @@ impl<'a> Command<'a, MkdirError> for Mkdir<'a> {
                 writeln!(output.stdout, "dir {} was created", arg)?;
             }
         }
-        Ok(true)
+        Ok(SUCCESS)
     }
 
     fn help() {
@@ -112,6 +123,8 @@ pub enum MkdirError {
     UnclosedBrecker,
 }
 
+impl ErrorStatus for MkdirError {}
+
 impl fmt::Display for MkdirError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -1,13 +1,14 @@
 use std::{
     fmt, fs,
-    io::{self, PipeReader, Write},
+    io::{self, Write},
     path::{Path, PathBuf},
 };
 
 use crate::command_build::{
     build::{BuildError, CommandBuild},
-    command::{Command, CommandError},
+    command::{Command, CommandError, ErrorStatus, FAILURE, SUCCESS, Status},
     parse::CommandBackPack,
+    plumbing::InputStream,
 };
 
 pub struct Rm<'a> {
@@ -17,10 +18,12 @@ pub struct Rm<'a> {
 }
 
 impl<'a> CommandBuild<'a, RmError> for Rm<'a> {
+    const FLAGS: &'static [&'static str] = &["-rf", "--remove-force"];
+
     fn new_obj(
         args: Vec<&'a str>,
         path: &'a Path,
-        _pipe: Option<&'a PipeReader>,
+        _pipe: Option<&'a InputStream>,
     ) -> Result<Box<dyn Command<'a, RmError> + 'a>, CommandError<'a, RmError>> {
         let mut i = 0;
         let mut names: Vec<&str> = Vec::new();
@@ -64,30 +67,41 @@ impl<'a> Command<'a, RmError> for Rm<'a> {
     fn run(
         self: Box<Self>,
         output: &mut CommandBackPack,
-    ) -> Result<bool, CommandError<'a, RmError>> {
+    ) -> Result<Status, CommandError<'a, RmError>> {
         let dangerous = [
             "/", "/*", "/etc", "/bin", "/usr", "/lib", "*", ".", "..", "/var", "/sys", "/proc",
             "/dev", "/boot",
         ];
-        let mut exit_code = true;
+        let mut exit_code = SUCCESS;
         for arg in self.names {
-            for i in dangerous {
-                if arg.contains(i) {
-                    writeln!(
-                        output.stdout,
-                        "sorry, my creator forbid me to\n\
-                        (delete those dirs/delete it like that)\n\
-                        pls use the system 'rm' for it\n\
-                        or you can edit my code in command_list/rm.rs file! shu!"
-                    )?;
-                    continue;
-                }
+            // the shell has already expanded globs, so only the exact names
+            // (or a path that leads to one of them) are refused
+            let trimmed = match arg.trim_end_matches('/') {
+                "" => "/",
+                trimmed => trimmed,
+            };
+            let real = self.path.join(arg).canonicalize().ok();
+            if dangerous.iter().any(|danger| {
+                *danger == trimmed
+                    || real
+                        .as_deref()
+                        .is_some_and(|real| real == Path::new(danger))
+            }) {
+                writeln!(
+                    output.stdout,
+                    "sorry, my creator forbid me to\n\
+                    (delete those dirs/delete it like that)\n\
+                    pls use the system 'rm' for it\n\
+                    or you can edit my code in command_list/rm.rs file! shu!"
+                )?;
+                exit_code = FAILURE;
+                continue;
             }
             if let Err(e) = Self::remove(self.path, arg, self.dir) {
                 match e {
                     RmError::IsDir(_) => {
                         write!(output.stderr, "{}", e)?;
-                        exit_code = false;
+                        exit_code = FAILURE;
                     }
                     _ => return Err(CommandError::Other("rm", e)),
                 }
@@ -125,6 +139,8 @@ pub enum RmError {
     IsDir(PathBuf),
 }
 
+impl ErrorStatus for RmError {}
+
 impl fmt::Display for RmError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
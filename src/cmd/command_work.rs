@@ -1,77 +1,1119 @@
 use std::{
-    fmt,
-    io::PipeReader,
+    env, fmt, fs,
+    io::{self, PipeWriter, Write},
+    mem,
+    os::unix::{
+        fs::PermissionsExt,
+        process::{CommandExt, ExitStatusExt},
+    },
     path::{Path, PathBuf},
+    process::{self, Stdio},
+    sync::Arc,
+    thread::{self, ScopedJoinHandle},
 };
 
 use crate::command_build::{
+    ast::{
+        AndOr, Command, Compound, Connector, ListItem, Pipeline, Redirect, RedirectOp,
+        SimpleCommand,
+    },
     build::CommandBuild,
-    parse::{CommandBackPack, split_args},
+    command::{
+        CommandError, ErrorStatus, FAILURE, NOT_EXECUTABLE, NOT_FOUND, SUCCESS, Status, USAGE,
+    },
+    expand::{ExpandError, Variables, expand_here_doc, expand_pattern, expand_value, expand_word},
+    glob::matches,
+    parse::{CommandBackPack, OutputFile, parse_with_aliases},
+    plumbing::{InputStream, Plumbing},
 };
 
 use crate::command_list::{
     Cat, CatError, Grep, GrepError, HeadTail, HeadTailError, Ls, LsError, Mkdir, MkdirError, Rm,
     RmError,
 };
+use crate::shell::{
+    Flow, Frame, Shell, builtins,
+    jobs::{Control, State},
+    signals::{self, signal_name},
+};
+
+/// Commands implemented in `command_list`, run in a thread.
+pub const BUILTINS: [&str; 6] = ["grep", "cat", "head-tail", "ls", "mkdir", "rm"];
+
+/// The options one of `BUILTINS` accepts.
+pub fn builtin_flags(name: &str) -> Option<&'static [&'static str]> {
+    Some(match name {
+        "grep" => <Grep as CommandBuild<GrepError>>::FLAGS,
+        "cat" => <Cat as CommandBuild<CatError>>::FLAGS,
+        "head-tail" => <HeadTail as CommandBuild<HeadTailError>>::FLAGS,
+        "ls" => <Ls as CommandBuild<LsError>>::FLAGS,
+        "mkdir" => <Mkdir as CommandBuild<MkdirError>>::FLAGS,
+        "rm" => <Rm as CommandBuild<RmError>>::FLAGS,
+        _ => return None,
+    })
+}
+
+/// `exec` error for a file that is executable but not a binary the kernel
+/// knows how to start (a script without a `#!` line).
+const ENOEXEC: i32 = 8;
+
+/// A pipeline stage that was started and has to be waited for.
+enum Running<'scope> {
+    Thread(ScopedJoinHandle<'scope, Status>),
+    Process(process::Child),
+    Failed(Status),
+}
+
+/// How a stage ended, or the program back when it stopped in the
+/// foreground.
+enum Waited {
+    Status(Status),
+    Stopped(process::Child),
+}
+
+impl Running<'_> {
+    fn wait(self, control: &Control) -> Waited {
+        match self {
+            Self::Thread(handle) => Waited::Status(handle.join().unwrap_or(FAILURE)),
+            Self::Process(child) => match wait_child(&child, control) {
+                Some(status) => Waited::Status(status),
+                None => Waited::Stopped(child),
+            },
+            Self::Failed(code) => Waited::Status(code),
+        }
+    }
+}
 
-fn run<'a, E, B>(vec: Vec<&'a str>, path: &'a Path, pipe: Option<&'a PipeReader>) -> bool
+/// Waits for a program to end, following its stops and restarts: a job
+/// only changes state, the commands of the shell itself stop waiting
+/// (`None`) and leave the program to a new job.
+fn wait_child(child: &process::Child, control: &Control) -> Option<Status> {
+    let pid = child.id() as libc::pid_t;
+    loop {
+        let mut status = 0;
+        // SAFETY: waits for a child of the shell, status is plain data
+        if unsafe { libc::waitpid(pid, &mut status, libc::WUNTRACED | libc::WCONTINUED) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            eprintln!("shu: error with waiting for process: {}", e);
+            control.ended(pid);
+            return Some(FAILURE);
+        }
+        if libc::WIFSTOPPED(status) {
+            if !control.is_job() {
+                return None;
+            }
+            control.set_state(State::Stopped);
+        } else if libc::WIFCONTINUED(status) {
+            control.set_state(State::Running);
+        } else {
+            // Ctrl-C ends what comes after the program too
+            if libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGINT {
+                control.cancel().set();
+            }
+            control.ended(pid);
+            return Some(exit_code(process::ExitStatus::from_raw(status)));
+        }
+    }
+}
+
+/// Ctrl-Z stopped programs of the shell's own commands: they become a job,
+/// and the shell goes on with what comes after them.
+fn suspend(shell: &Shell, children: Vec<process::Child>, text: &str) -> Status {
+    let control = Arc::new(Control::job(State::Stopped));
+    let pids: Vec<libc::pid_t> = children
+        .iter()
+        .map(|child| child.id() as libc::pid_t)
+        .collect();
+    shell.control.hand_over(&pids, &control);
+    let waiter = Arc::clone(&control);
+    let id = shell.jobs.spawn(text.to_string(), control, move || {
+        // every one is waited for, the status is the one of the last
+        let mut status = SUCCESS;
+        for child in &children {
+            status = wait_child(child, &waiter).unwrap_or(status);
+        }
+        status
+    });
+    shell.jobs.shown(id);
+    if let Ok(job) = shell.jobs.find(Some(&id.to_string())) {
+        println!("\n{}", job.line(State::Stopped, false));
+    }
+    128 + libc::SIGTSTP
+}
+
+/// A process killed by a signal reports `128 + signal`, as in other shells.
+fn exit_code(status: process::ExitStatus) -> Status {
+    match status.code() {
+        Some(code) => code,
+        None => 128 + status.signal().unwrap_or(0),
+    }
+}
+
+fn run<'a, E, B>(
+    vec: Vec<&'a str>,
+    path: &'a Path,
+    redirects: &'a [Redirect],
+    pipe: Option<&'a InputStream>,
+    output: CommandBackPack,
+) -> Status
 where
     B: CommandBuild<'a, E>,
-    E: fmt::Display,
+    E: fmt::Display + ErrorStatus,
 {
-    let (mut str, args, (pipe_next, pipe_args)) = match CommandBackPack::parser(vec, path) {
-        Ok(args) => (args.commandbp, args.args_left, args.pipe_part),
+    let (mut str, args) = match CommandBackPack::parser(vec, path, redirects, output) {
+        Ok(args) => (args.commandbp, args.args_left),
         Err(e) => {
             eprintln!("{}", e);
-            return false;
+            return USAGE;
         }
     };
     match B::new_obj(args, path, pipe) {
         Ok(command) => match command.run(&mut str) {
+            // the reader side of the pipe is gone (`... | head-tail`), nothing to report
+            Err(CommandError::WriteError(e)) if e.kind() == io::ErrorKind::BrokenPipe => FAILURE,
             Err(e) => {
                 if let Err(e) = writeln!(str.stderr, "{}", e) {
                     println!("error with write in stderr, so here the error: {}", e);
                 }
-                false
+                e.status()
             }
             Ok(code) => {
-                if let Some(args_pipe) = pipe_args
-                    && let Some(pipe) = pipe_next
-                {
-                    let _ = str.stdout.flush();
-                    drop(str);
-                    set(args_pipe, path, Some(&pipe))
-                } else {
-                    code
-                }
+                let _ = str.stdout.flush();
+                code
             }
         },
         Err(e) => {
-            if let Err(e) = writeln!(str.stderr, "{}", e) {
-                println!("error with write into stderr, so error: {}", e);
+            if let Err(err) = writeln!(str.stderr, "{}", e) {
+                println!("error with write into stderr, so error: {}", err);
             }
-            false
+            e.status()
+        }
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    match path.metadata() {
+        Ok(meta) => meta.is_file() && meta.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+pub fn find_program(name: &str, path: &Path, search: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        let program = path.join(name);
+        return is_executable(&program).then_some(program);
+    }
+    env::split_paths(search)
+        .map(|dir| path.join(dir).join(name))
+        .find(|program| is_executable(program))
+}
+
+/// Starts the program; with job control it joins the process group of its
+/// pipeline (or starts it) and takes the terminal in the foreground.
+fn spawn_with(
+    shell: &Shell,
+    mut command: process::Command,
+    path: &Path,
+    env: &[(String, String)],
+    pipe: Option<&InputStream>,
+    output: &CommandBackPack,
+) -> io::Result<process::Child> {
+    let stdin = match pipe {
+        Some(pipe) => pipe.stdio()?,
+        None => Stdio::inherit(),
+    };
+    command
+        .current_dir(path)
+        .env_clear()
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(stdin)
+        .stdout(output.stdout.stdio()?)
+        .stderr(output.stderr.stdio()?);
+    if let Some(tty) = shell.terminal {
+        let foreground = shell.control.is_foreground();
+        command.process_group(shell.control.pgid());
+        // SAFETY: only async-signal-safe calls between fork and exec. The
+        // program takes the terminal itself, so it never reads from it
+        // before it has it.
+        unsafe {
+            command.pre_exec(move || {
+                if foreground {
+                    libc::tcsetpgrp(tty, libc::getpgrp());
+                }
+                libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+                Ok(())
+            });
+        }
+    }
+    let child = match command.spawn() {
+        // the programs of the group all ended meanwhile: a new one
+        Err(e) if e.raw_os_error() == Some(libc::EPERM) && shell.terminal.is_some() => {
+            command.process_group(0);
+            command.spawn()
+        }
+        result => result,
+    }?;
+    shell
+        .control
+        .started(child.id() as libc::pid_t, shell.terminal);
+    Ok(child)
+}
+
+fn spawn(
+    shell: &Shell,
+    program: &Path,
+    args: &[&str],
+    path: &Path,
+    env: &[(String, String)],
+    pipe: Option<&InputStream>,
+    output: &CommandBackPack,
+) -> io::Result<process::Child> {
+    let _ = io::stdout().flush();
+    let mut command = process::Command::new(program);
+    command.args(args);
+    match spawn_with(shell, command, path, env, pipe, output) {
+        // no `#!` line: run it as a shu script, as other shells do with theirs
+        Err(e) if e.raw_os_error() == Some(ENOEXEC) => {
+            let mut command = process::Command::new(env::current_exe()?);
+            command.arg(program).args(args);
+            spawn_with(shell, command, path, env, pipe, output)
+        }
+        result => result,
+    }
+}
+
+/// Spawns the program without waiting for it; the pipe ends given here are
+/// closed in the shell as soon as the child owns its copies.
+fn start_external<'a>(
+    shell: &Shell,
+    vec: Vec<&'a str>,
+    path: &Path,
+    redirects: &'a [Redirect],
+    env: &[(String, String)],
+    pipe: Option<&InputStream>,
+    output: CommandBackPack,
+) -> Result<process::Child, Status> {
+    let name = vec[0];
+    let search = env
+        .iter()
+        .find(|(name, _)| name == "PATH")
+        .map_or("", |(_, value)| value.as_str());
+    let Some(program) = find_program(name, path, search) else {
+        eprintln!("shu: unknown command: {}", name);
+        return Err(NOT_FOUND);
+    };
+    let (mut str, args) = match CommandBackPack::parser(vec, path, redirects, output) {
+        Ok(args) => (args.commandbp, args.args_left),
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(USAGE);
+        }
+    };
+    spawn(shell, &program, &args, path, env, pipe, &str).map_err(|e| {
+        if let Err(e) = writeln!(str.stderr, "shu: {}: {}", name, e) {
+            println!("error with write into stderr, so error: {}", e);
+        }
+        NOT_EXECUTABLE
+    })
+}
+
+fn external<'a>(
+    shell: &Shell,
+    vec: Vec<&'a str>,
+    path: &Path,
+    redirects: &'a [Redirect],
+    env: &[(String, String)],
+    pipe: Option<&InputStream>,
+    output: CommandBackPack,
+) -> Status {
+    let text = vec.join(" ");
+    match start_external(shell, vec, path, redirects, env, pipe, output) {
+        Ok(child) => match Running::Process(child).wait(&shell.control) {
+            Waited::Status(status) => status,
+            Waited::Stopped(child) => suspend(shell, vec![child], &text),
+        },
+        Err(code) => code,
+    }
+}
+
+pub fn set<'a>(
+    shell: &Shell,
+    vec: Vec<&'a str>,
+    path: &'a Path,
+    redirects: &'a [Redirect],
+    env: &[(String, String)],
+    pipe_mode: Option<&'a InputStream>,
+    output: CommandBackPack,
+) -> Status {
+    let cancel = shell.control.cancel();
+    cancel.watch();
+    let code = match vec[0] {
+        "grep" => run::<'_, GrepError, Grep>(vec, path, redirects, pipe_mode, output),
+        "cat" => run::<'_, CatError, Cat>(vec, path, redirects, pipe_mode, output),
+        "head-tail" => run::<'_, HeadTailError, HeadTail>(vec, path, redirects, pipe_mode, output),
+        "ls" => run::<'_, LsError, Ls>(vec, path, redirects, pipe_mode, output),
+        "mkdir" => run::<'_, MkdirError, Mkdir>(vec, path, redirects, pipe_mode, output),
+        "rm" => run::<'_, RmError, Rm>(vec, path, redirects, pipe_mode, output),
+        _ => return external(shell, vec, path, redirects, env, pipe_mode, output),
+    };
+    if cancel.is_set() {
+        shell.control.cancelled_status()
+    } else {
+        code
+    }
+}
+
+/// Where commands read and write unless they redirect: the terminal at the
+/// top, a pipe for a stage of a pipeline, or what a compound command like
+/// `while ...; done > log` was redirected to.
+#[derive(Default)]
+pub struct Io<'a> {
+    pub stdin: Option<&'a InputStream>,
+    pub output: CommandBackPack,
+}
+
+impl<'a> Io<'a> {
+    /// The output for one stage of a pipeline: the pipe to the next stage,
+    /// or wherever this one writes for the last stage.
+    fn piped(&self, pipe: Option<PipeWriter>) -> Result<CommandBackPack, Status> {
+        let piped = || {
+            Ok(CommandBackPack {
+                stdout: match pipe {
+                    Some(pipe) => OutputFile::Pipe(pipe),
+                    None => self.output.stdout.try_clone()?,
+                },
+                stderr: self.output.stderr.try_clone()?,
+            })
+        };
+        piped().map_err(|e: io::Error| {
+            eprintln!("shu: {}", e);
+            FAILURE
+        })
+    }
+
+    fn try_clone(&self) -> Result<Self, Status> {
+        Ok(Self {
+            stdin: self.stdin,
+            output: self.piped(None)?,
+        })
+    }
+}
+
+/// A simple command after expansion, ready to be dispatched.
+struct Expanded {
+    assignments: Vec<(String, String)>,
+    args: Vec<String>,
+    redirects: Vec<Redirect>,
+}
+
+/// Lets `A=1 B=$A` see the assignments on its left.
+struct Assigned<'a> {
+    shell: &'a Shell,
+    assignments: &'a [(String, String)],
+}
+
+impl Variables for Assigned<'_> {
+    fn var(&self, name: &str) -> Option<String> {
+        match self.assignments.iter().rev().find(|(set, _)| set == name) {
+            Some((_, value)) => Some(value.clone()),
+            None => self.shell.var(name),
+        }
+    }
+
+    fn params(&self) -> Vec<String> {
+        self.shell.params()
+    }
+
+    fn nounset(&self) -> bool {
+        self.shell.nounset()
+    }
+}
+
+/// A word as `set -x` shows it: quoted when it wouldn't read back as one.
+fn traced(word: &str) -> String {
+    if !word.is_empty()
+        && !word.contains(|ch: char| ch.is_whitespace() || "'\"\\$&;|<>()*?[]{}!#`".contains(ch))
+    {
+        word.to_string()
+    } else {
+        builtins::single_quoted(word)
+    }
+}
+
+/// Arguments of a command or of `for ... in`: expanded and globbed.
+fn expand_words(shell: &Shell, words: &[String]) -> Result<Vec<String>, ExpandError> {
+    let glob = shell.glob();
+    let mut args = Vec::new();
+    for word in words {
+        args.extend(expand_word(word, shell, Some(&glob))?);
+    }
+    Ok(args)
+}
+
+fn expand_redirects(shell: &Shell, redirects: &[Redirect]) -> Result<Vec<Redirect>, ExpandError> {
+    let mut expanded = Vec::new();
+    for redirect in redirects {
+        let target = match redirect.op {
+            RedirectOp::HereDoc { expand: true } => expand_here_doc(&redirect.target, shell)?,
+            RedirectOp::HereDoc { expand: false } => redirect.target.clone(),
+            RedirectOp::HereString => expand_value(&redirect.target, shell)? + "\n",
+            _ => expand_word(&redirect.target, shell, None)?.join(" "),
+        };
+        let op = match redirect.op {
+            // without noclobber `>` overwrites like `>|`
+            RedirectOp::Write if !shell.options.noclobber => RedirectOp::Clobber,
+            op => op,
+        };
+        expanded.push(Redirect {
+            fd: redirect.fd,
+            op,
+            target,
+        });
+    }
+    Ok(expanded)
+}
+
+/// Opens `< file`, `<< EOF` and `<<< word`, which take the place of the
+/// stream the command would read from otherwise.
+fn open_input(redirects: &[Redirect], path: &Path) -> Result<Option<InputStream>, Status> {
+    InputStream::redirected(redirects, path).map_err(|e| {
+        eprint!("{}", e);
+        USAGE
+    })
+}
+
+impl Expanded {
+    fn new(shell: &Shell, command: &SimpleCommand) -> Result<Self, ExpandError> {
+        let mut assignments = Vec::new();
+        for (name, value) in &command.assignments {
+            let assigned = Assigned {
+                shell,
+                assignments: &assignments,
+            };
+            let value = expand_value(value, &assigned)?;
+            assignments.push((name.clone(), value));
+        }
+        Ok(Self {
+            assignments,
+            args: expand_words(shell, &command.words)?,
+            redirects: expand_redirects(shell, &command.redirects)?,
+        })
+    }
+
+    /// `set -x`: the command as it runs, after `+ ` on stderr.
+    fn trace(&self) {
+        let words: Vec<String> = self
+            .assignments
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, traced(value)))
+            .chain(self.args.iter().map(|arg| traced(arg)))
+            .collect();
+        eprintln!("+ {}", words.join(" "));
+    }
+
+    /// What a program started for this command gets as environment:
+    /// the exported variables plus the `NAME=value` prefixes.
+    fn environment(&self, shell: &Shell) -> Vec<(String, String)> {
+        let mut env = shell.environment();
+        env.retain(|(name, _)| !self.assignments.iter().any(|(set, _)| set == name));
+        env.extend(self.assignments.iter().cloned());
+        env
+    }
+
+    /// Runs in a thread of the shell rather than as a child process.
+    fn is_builtin(&self, shell: &Shell) -> bool {
+        match self.args.first() {
+            Some(name) => {
+                BUILTINS.contains(&name.as_str())
+                    || builtins::lookup(name).is_some()
+                    || shell.functions.contains_key(name)
+            }
+            None => true,
+        }
+    }
+}
+
+fn command(shell: &mut Shell, expanded: &Expanded, io: Io) -> Status {
+    if shell.options.xtrace {
+        expanded.trace();
+    }
+    let input = match open_input(&expanded.redirects, &shell.now_dir) {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+    let pipe = input.as_ref().or(io.stdin);
+    if let Some(body) = expanded
+        .args
+        .first()
+        .and_then(|name| shell.functions.get(name))
+    {
+        let body = Arc::clone(body);
+        let path = shell.now_dir.clone();
+        return match CommandBackPack::parser(Vec::new(), &path, &expanded.redirects, io.output) {
+            Ok(parsed) => {
+                let io = Io {
+                    stdin: pipe,
+                    output: parsed.commandbp,
+                };
+                call(shell, &body, expanded, io)
+            }
+            Err(e) => {
+                eprint!("{}", e);
+                USAGE
+            }
+        };
+    }
+    let vec: Vec<&str> = expanded.args.iter().map(|x| x.as_str()).collect();
+    let builtin = match vec.first() {
+        Some(name) => builtins::lookup(name),
+        // only assignments and redirections: `A=1 > file`
+        None => {
+            for (name, value) in &expanded.assignments {
+                shell.set_var(name, value.clone());
+            }
+            let path = &shell.now_dir;
+            return match CommandBackPack::parser(vec, path, &expanded.redirects, io.output) {
+                Ok(_) => SUCCESS,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    USAGE
+                }
+            };
+        }
+    };
+    let Some(builtin) = builtin else {
+        let path = shell.now_dir.clone();
+        let env = expanded.environment(shell);
+        return set(
+            shell,
+            vec,
+            &path,
+            &expanded.redirects,
+            &env,
+            pipe,
+            io.output,
+        );
+    };
+    let path = shell.now_dir.clone();
+    match CommandBackPack::parser(vec, &path, &expanded.redirects, io.output) {
+        Ok(parsed) => {
+            let mut str = parsed.commandbp;
+            let code = builtin(shell, &parsed.args_left, &mut str);
+            let _ = str.stdout.flush();
+            code
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            USAGE
+        }
+    }
+}
+
+/// Runs a function: the arguments become `$1`..., `NAME=value` prefixes
+/// and `local` variables only last for the call.
+fn call(shell: &mut Shell, body: &Command, expanded: &Expanded, io: Io) -> Status {
+    let params = [shell.args[0].clone()]
+        .into_iter()
+        .chain(expanded.args[1..].iter().cloned())
+        .collect();
+    let saved = mem::replace(&mut shell.args, params);
+    let mut frame = Frame::new();
+    for (name, value) in &expanded.assignments {
+        frame
+            .entry(name.clone())
+            .or_insert_with(|| shell.vars.get(name).cloned());
+        shell.set_var(name, value.clone());
+    }
+    shell.locals.push(frame);
+    shell.depth += 1;
+    let mut code = match Stage::new(shell, body) {
+        Ok(stage) => stage.run(shell, io),
+        Err(e) => {
+            eprintln!("{}", e);
+            FAILURE
         }
+    };
+    if let Some(Flow::Return(returned)) = shell.flow {
+        shell.flow = None;
+        code = returned;
     }
+    shell.depth -= 1;
+    for (name, old) in shell.locals.pop().unwrap_or_default() {
+        match old {
+            Some(var) => shell.vars.insert(name, var),
+            None => shell.vars.remove(&name),
+        };
+    }
+    shell.args = saved;
+    code
 }
 
-pub fn set(vec: Vec<&str>, path: &Path, pipe_mode: Option<&PipeReader>) -> bool {
-    match vec[0] {
-        "grep" => run::<'_, GrepError, Grep>(vec, path, pipe_mode),
-        "cat" => run::<'_, CatError, Cat>(vec, path, pipe_mode),
-        "head-tail" => run::<'_, HeadTailError, HeadTail>(vec, path, pipe_mode),
-        "ls" => run::<'_, LsError, Ls>(vec, path, pipe_mode),
-        "mkdir" => run::<'_, MkdirError, Mkdir>(vec, path, pipe_mode),
-        "rm" => run::<'_, RmError, Rm>(vec, path, pipe_mode),
-        _ => {
-            eprintln!("shu: unknown command: {}", vec[0]);
+/// Whether a loop goes on after its body (or condition) ran, following a
+/// `break N`/`continue N` or an `exit`/`return` in it.
+fn next_round(shell: &mut Shell) -> bool {
+    if shell.exit.is_some() || shell.control.cancel().is_set() {
+        return false;
+    }
+    match shell.flow.take() {
+        None | Some(Flow::Continue(1)) => true,
+        Some(Flow::Break(1)) => false,
+        Some(Flow::Break(n)) => {
+            shell.flow = Some(Flow::Break(n - 1));
+            false
+        }
+        Some(Flow::Continue(n)) => {
+            shell.flow = Some(Flow::Continue(n - 1));
+            false
+        }
+        Some(flow @ Flow::Return(_)) => {
+            shell.flow = Some(flow);
             false
         }
     }
 }
 
-pub fn todo(command: &str, path: PathBuf) -> bool {
-    let vec_string: Vec<String> = split_args(command);
-    let vec = vec_string.iter().map(|x| x.as_str()).collect();
-    set(vec, &path, None)
+fn compound(shell: &mut Shell, compound: &Compound, redirects: &[Redirect], io: Io) -> Status {
+    let redirects = match expand_redirects(shell, redirects) {
+        Ok(redirects) => redirects,
+        Err(e) => {
+            eprintln!("{}", e);
+            return FAILURE;
+        }
+    };
+    let input = match open_input(&redirects, &shell.now_dir) {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+    let output = match CommandBackPack::parser(Vec::new(), &shell.now_dir, &redirects, io.output) {
+        Ok(parsed) => parsed.commandbp,
+        Err(e) => {
+            eprint!("{}", e);
+            return USAGE;
+        }
+    };
+    let io = Io {
+        stdin: input.as_ref().or(io.stdin),
+        output,
+    };
+    match compound {
+        Compound::Group(body) => list(shell, body, &io),
+        Compound::If {
+            branches,
+            otherwise,
+        } => {
+            for (condition, body) in branches {
+                let test = condition_list(shell, condition, &io);
+                if shell.stopped() {
+                    return test;
+                }
+                if test == SUCCESS {
+                    return list(shell, body, &io);
+                }
+            }
+            match otherwise {
+                Some(body) => list(shell, body, &io),
+                None => SUCCESS,
+            }
+        }
+        Compound::Loop {
+            until,
+            condition,
+            body,
+        } => {
+            shell.loops += 1;
+            let mut code = SUCCESS;
+            loop {
+                let test = condition_list(shell, condition, &io);
+                if !next_round(shell) || (test == SUCCESS) == *until {
+                    break;
+                }
+                code = list(shell, body, &io);
+                if !next_round(shell) {
+                    break;
+                }
+            }
+            shell.loops -= 1;
+            code
+        }
+        Compound::For { name, words, body } => {
+            let values = match words {
+                Some(words) => match expand_words(shell, words) {
+                    Ok(values) => values,
+                    Err(e) => return expand_failed(shell, &e),
+                },
+                None => shell.params(),
+            };
+            shell.loops += 1;
+            let mut code = SUCCESS;
+            for value in values {
+                shell.set_var(name, value);
+                code = list(shell, body, &io);
+                if !next_round(shell) {
+                    break;
+                }
+            }
+            shell.loops -= 1;
+            code
+        }
+        Compound::Case { word, arms } => {
+            let word = match expand_value(word, shell) {
+                Ok(word) => word,
+                Err(e) => return expand_failed(shell, &e),
+            };
+            for arm in arms {
+                for pattern in &arm.patterns {
+                    match expand_pattern(pattern, shell) {
+                        Ok(pattern) if matches(&pattern, &word) => {
+                            return list(shell, &arm.body, &io);
+                        }
+                        Ok(_) => {}
+                        Err(e) => return expand_failed(shell, &e),
+                    }
+                }
+            }
+            SUCCESS
+        }
+    }
+}
+
+/// Tells why a word couldn't be expanded. An unset variable under `set -u`
+/// also ends a shell that isn't interactive, as POSIX wants.
+fn expand_failed(shell: &mut Shell, e: &ExpandError) -> Status {
+    eprintln!("{}", e);
+    if matches!(e, ExpandError::Unbound(_)) && shell.terminal.is_none() {
+        shell.exit = shell.exit.or(Some(FAILURE));
+    }
+    FAILURE
+}
+
+/// A stage of a pipeline: simple commands are expanded before anything
+/// starts, compound ones only as they run.
+enum Stage<'c> {
+    Simple(Expanded),
+    Compound(&'c Compound, &'c [Redirect]),
+    Function(&'c str, &'c Command),
+}
+
+impl<'c> Stage<'c> {
+    fn new(shell: &Shell, command: &'c Command) -> Result<Self, ExpandError> {
+        Ok(match command {
+            Command::Simple(simple) => Self::Simple(Expanded::new(shell, simple)?),
+            Command::Compound(compound, redirects) => Self::Compound(compound, redirects),
+            Command::Function { name, body } => Self::Function(name, body),
+        })
+    }
+
+    fn run(&self, shell: &mut Shell, io: Io) -> Status {
+        match self {
+            Self::Simple(expanded) => command(shell, expanded, io),
+            Self::Compound(body, redirects) => compound(shell, body, redirects, io),
+            Self::Function(name, body) => {
+                let body = Arc::new(Command::clone(body));
+                shell.functions.insert(name.to_string(), body);
+                SUCCESS
+            }
+        }
+    }
+
+    /// The command if it is an external program, everything else runs in a
+    /// thread of the shell.
+    fn external(&self, shell: &Shell) -> Option<&Expanded> {
+        match self {
+            Self::Simple(expanded) if !expanded.is_builtin(shell) => Some(expanded),
+            _ => None,
+        }
+    }
+}
+
+/// Starts every stage at once with an OS pipe between neighbours, so data
+/// streams through instead of piling up in the kernel buffer: builtins and
+/// compound commands run in their own thread, external programs as child
+/// processes. The status of the pipeline is the one of the last stage.
+pub fn pipeline(shell: &mut Shell, pipeline: &Pipeline, io: &Io) -> Status {
+    let stages: Vec<Stage> = match pipeline
+        .commands
+        .iter()
+        .map(|command| Stage::new(shell, command))
+        .collect()
+    {
+        Ok(stages) => stages,
+        Err(e) => return expand_failed(shell, &e),
+    };
+    // the first program started leads the process group of the pipeline,
+    // the programs of nested ones join it
+    let leader = shell.control.pgid() == 0;
+    let status = run_stages(shell, &stages, &pipeline.text, io);
+    if leader {
+        shell.control.release(shell.terminal);
+    }
+    status
+}
+
+fn run_stages(shell: &mut Shell, stages: &[Stage], text: &str, io: &Io) -> Status {
+    if let [stage] = stages {
+        return match io.try_clone() {
+            Ok(io) => stage.run(shell, io),
+            Err(code) => code,
+        };
+    }
+    let plumbing = match Plumbing::connect(stages.len()) {
+        Ok(plumbing) => plumbing,
+        Err(e) => {
+            eprintln!("{}", e);
+            return FAILURE;
+        }
+    };
+    let shell = &*shell;
+    thread::scope(|scope| {
+        let running: Vec<Running> = stages
+            .iter()
+            .zip(plumbing)
+            .map(|(stage, Plumbing { stdin, stdout })| {
+                let output = match io.piped(stdout) {
+                    Ok(output) => output,
+                    Err(code) => return Running::Failed(code),
+                };
+                let Some(stage) = stage.external(shell) else {
+                    let mut subshell = shell.subshell();
+                    let outer = io.stdin;
+                    return Running::Thread(scope.spawn(move || {
+                        let stdin = stdin.as_ref().or(outer);
+                        stage.run(&mut subshell, Io { stdin, output })
+                    }));
+                };
+                if shell.options.xtrace {
+                    stage.trace();
+                }
+                let input = match open_input(&stage.redirects, &shell.now_dir) {
+                    Ok(input) => input,
+                    Err(code) => return Running::Failed(code),
+                };
+                let stdin = input.as_ref().or(stdin.as_ref()).or(io.stdin);
+                let vec = stage.args.iter().map(|x| x.as_str()).collect();
+                let (path, env) = (&shell.now_dir, stage.environment(shell));
+                let redirects = &stage.redirects;
+                match start_external(shell, vec, path, redirects, &env, stdin, output) {
+                    Ok(child) => Running::Process(child),
+                    Err(code) => Running::Failed(code),
+                }
+            })
+            .collect();
+        let mut statuses = vec![SUCCESS; running.len()];
+        let mut stopped = Vec::new();
+        let mut threads = Vec::new();
+        // programs first: the Ctrl-C that ends one cancels the builtins,
+        // which aren't in the process group that gets it
+        for (index, stage) in running.into_iter().enumerate() {
+            match stage {
+                Running::Thread(_) => threads.push((index, stage)),
+                // in the same process group, stopped as well
+                Running::Process(child) if !stopped.is_empty() => stopped.push(child),
+                stage => match stage.wait(&shell.control) {
+                    Waited::Status(code) => statuses[index] = code,
+                    Waited::Stopped(child) => stopped.push(child),
+                },
+            }
+        }
+        for (index, stage) in threads {
+            if let Waited::Status(code) = stage.wait(&shell.control) {
+                statuses[index] = code;
+            }
+        }
+        let status = if shell.options.pipefail {
+            statuses.iter().rev().find(|&&code| code != SUCCESS)
+        } else {
+            statuses.last()
+        };
+        let status = status.copied().unwrap_or(SUCCESS);
+        if stopped.is_empty() {
+            status
+        } else {
+            suspend(shell, stopped, text)
+        }
+    })
+}
+
+/// The condition of an `if`, `while` or `until`.
+fn condition_list(shell: &mut Shell, condition: &[ListItem], io: &Io) -> Status {
+    shell.testing += 1;
+    let code = list(shell, condition, io);
+    shell.testing -= 1;
+    code
+}
+
+/// Runs a pipeline of an and-or list, a condition for the next one unless
+/// it is the last.
+fn and_or_part(shell: &mut Shell, part: &Pipeline, test: bool, io: &Io) -> Status {
+    shell.testing += usize::from(test);
+    shell.reported = false;
+    let code = pipeline(shell, part, io);
+    shell.testing -= usize::from(test);
+    shell.last_status = code;
+    code
+}
+
+pub fn and_or(shell: &mut Shell, and_or: &AndOr, io: &Io) -> Status {
+    let mut tested = !and_or.rest.is_empty();
+    let mut code = and_or_part(shell, &and_or.first, tested, io);
+    let mut last = &and_or.first;
+    for (index, (connector, next)) in and_or.rest.iter().enumerate() {
+        if shell.stopped() {
+            break;
+        }
+        match connector {
+            Connector::And if code != SUCCESS => continue,
+            Connector::Or if code == SUCCESS => continue,
+            _ => {}
+        }
+        tested = index + 1 < and_or.rest.len();
+        code = and_or_part(shell, next, tested, io);
+        last = next;
+    }
+    run_signal_traps(shell);
+    if shell.control.cancel().is_set() {
+        code = shell.control.cancelled_status();
+        shell.last_status = code;
+    }
+    // a compound command or function already told about the command that
+    // failed in it, and `exit` doesn't fail
+    let compound = matches!(last.commands.last(), Some(Command::Compound(..)));
+    let failed = code != SUCCESS && shell.exit.is_none() && !shell.reported;
+    if failed && !tested && shell.testing == 0 && !compound {
+        if !shell.trapping {
+            run_trap(shell, "ERR");
+        }
+        if shell.options.errexit {
+            shell.exit = shell.exit.or(Some(code));
+        }
+        shell.reported = true;
+    }
+    code
+}
+
+/// Runs the and-or list of a `... &` item as a job, in a thread with its
+/// own copy of the shell state; the shell itself goes on right away.
+fn background(shell: &mut Shell, item: &ListItem, io: &Io) {
+    let output = match io.piped(None) {
+        Ok(output) => output,
+        Err(_) => return,
+    };
+    let mut subshell = shell.subshell();
+    let control = Arc::new(Control::job(State::Running));
+    subshell.control = Arc::clone(&control);
+    let job = item.and_or.clone();
+    let id = shell.jobs.spawn(item.text.clone(), control, move || {
+        let io = Io {
+            stdin: None,
+            output,
+        };
+        and_or(&mut subshell, &job, &io)
+    });
+    if shell.terminal.is_some() {
+        println!("[{}]", id);
+    }
+}
+
+pub fn list(shell: &mut Shell, list: &[ListItem], io: &Io) -> Status {
+    let mut code = SUCCESS;
+    for item in list {
+        if item.background {
+            background(shell, item, io);
+            code = SUCCESS;
+        } else {
+            code = and_or(shell, &item.and_or, io);
+        }
+        shell.last_status = code;
+        if shell.stopped() {
+            break;
+        }
+    }
+    code
+}
+
+/// Runs what `trap` set for `name` (`EXIT`, `ERR` or a signal) as a
+/// command line of its own: `$?` stays as it was, and what was running goes
+/// on after it unless the trap calls `exit`. The trap of a signal handles
+/// it: after the one of `INT` the rest of the line isn't skipped.
+pub fn run_trap(shell: &mut Shell, name: &str) {
+    let Some(command) = shell.traps.get(name).filter(|command| !command.is_empty()) else {
+        return;
+    };
+    let command = command.clone();
+    let status = shell.last_status;
+    let exit = shell.exit.take();
+    let flow = shell.flow.take();
+    let cancelled = shell.control.cancel().is_set();
+    shell.control.cancel().clear();
+    shell.trapping = true;
+    todo(shell, &command);
+    shell.trapping = false;
+    shell.last_status = status;
+    shell.exit = shell.exit.or(exit);
+    shell.flow = flow;
+    if cancelled && name != "INT" {
+        shell.control.cancel().set();
+    }
+}
+
+/// The traps of the signals that arrived since the last look.
+pub fn run_signal_traps(shell: &mut Shell) {
+    if shell.trapping || shell.traps.is_empty() {
+        return;
+    }
+    for signal in signals::arrived() {
+        if let Some(name) = signal_name(signal) {
+            run_trap(shell, name);
+        }
+    }
+}
+
+/// Runs every command of a script file in `shell`: what `source` and
+/// `terminal script.shu` do.
+pub fn run_file(shell: &mut Shell, file: &Path) -> io::Result<Status> {
+    let script = fs::read_to_string(shell.now_dir.join(file))?;
+    Ok(todo(shell, &script))
+}
+
+pub fn todo(shell: &mut Shell, command: &str) -> Status {
+    match parse_with_aliases(command, &shell.aliases) {
+        Ok(parsed) => list(shell, &parsed, &Io::default()),
+        Err(e) => {
+            eprintln!("{}", e);
+            shell.last_status = USAGE;
+            USAGE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell() -> Shell {
+        Shell::new(env::temp_dir(), PathBuf::new())
+    }
+
+    #[test]
+    fn functions_with_locals_and_return() {
+        let mut shell = shell();
+        let code = todo(
+            &mut shell,
+            "x=out; f() { local x=in; y=$x$1; return 3; y=no; }; f 1",
+        );
+        assert_eq!(code, 3);
+        assert_eq!(shell.var("x").as_deref(), Some("out"));
+        assert_eq!(shell.var("y").as_deref(), Some("in1"));
+    }
+
+    #[test]
+    fn err_trap_runs_once_for_a_failing_function() {
+        let mut shell = shell();
+        todo(&mut shell, "trap 'seen=${seen}x' ERR; f() { false; }; f");
+        assert_eq!(shell.var("seen").as_deref(), Some("x"));
+        todo(&mut shell, "seen=; { false; }; false");
+        assert_eq!(shell.var("seen").as_deref(), Some("xx"));
+    }
 }
@@ -0,0 +1,616 @@
+//! Reads command lines from the terminal with emacs style editing:
+//!
+//! | keys | does |
+//! |---|---|
+//! | Ctrl-A / Home, Ctrl-E / End | start / end of the line |
+//! | Ctrl-B / Left, Ctrl-F / Right | one character back / forward |
+//! | Alt-B / Ctrl-Left, Alt-F / Ctrl-Right | one word back / forward |
+//! | Backspace, Ctrl-D / Delete | delete before / under the cursor |
+//! | Ctrl-W, Alt-D | cut the word before / after the cursor |
+//! | Ctrl-U, Ctrl-K | cut to the start / end of the line |
+//! | Ctrl-Y | paste what was cut last |
+//! | Ctrl-T | swap the two characters around the cursor |
+//! | Up / Ctrl-P, Down / Ctrl-N | previous / next line of an unfinished command, or of the history |
+//! | Right / Ctrl-F / End at the end | take the suggestion shown in grey |
+//! | Tab | complete the word before the cursor, or list what it can become |
+//! | Ctrl-R | search the history, see [`search`] |
+//! | Ctrl-L | clear the screen |
+//! | Ctrl-C | drop the line |
+//! | Ctrl-D on an empty line | end of input |
+//!
+//! Enter on an unfinished command (open quote, `if` without `fi`...) goes
+//! on on a new line of the same buffer instead of running it. The line is
+//! colored as it is typed, and the most recent history entry starting with
+//! it is suggested after the cursor.
+//!
+//! Without a terminal on both ends lines are read as they come.
+
+mod search;
+mod terminal;
+mod width;
+
+use std::io::{self, BufRead, Write, stdin, stdout};
+
+use terminal::{Key, RawMode, columns, is_tty, read_key};
+use width::{Layout, is_combining, str_width};
+
+pub enum Input {
+    Line(String),
+    /// Ctrl-C, the line was dropped.
+    Interrupted,
+    /// Ctrl-D on an empty line, or the input was closed.
+    Eof,
+}
+
+/// What the editor needs to know about the language it edits.
+pub trait Helper {
+    /// The command can't run yet (an open quote, `if` without `fi`...), so
+    /// Enter starts a new line instead.
+    fn incomplete(&self, _line: &str) -> bool {
+        false
+    }
+
+    /// What the text before `cursor` can be completed with.
+    fn complete(&self, _line: &str, _cursor: usize) -> Completion {
+        Completion::default()
+    }
+
+    /// The colors of the line, a later span wins over an earlier one.
+    fn highlight(&self, _line: &str) -> Vec<Span> {
+        Vec::new()
+    }
+
+    /// The history entries Ctrl-R finds for `query`, best first, only
+    /// those run in the current directory when `here`.
+    fn search(&self, _query: &str, _here: bool) -> Vec<Hit> {
+        Vec::new()
+    }
+}
+
+/// Bytes `start..end` of the line are drawn with `style`, an SGR escape
+/// sequence like `"\x1b[32m"`.
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub style: &'static str,
+}
+
+/// A history entry found by Ctrl-R.
+pub struct Hit {
+    pub text: String,
+    /// Bytes of `text` the query matched, underlined.
+    pub matches: Vec<usize>,
+    /// Shown under the line while the hit is: where and how it ran.
+    pub detail: String,
+}
+
+/// How the suggestion from the history is drawn.
+const SUGGESTION: &str = "\x1b[90m";
+/// How the characters Ctrl-R matched are drawn.
+const MATCH: &str = "\x1b[1;4m";
+const RESET: &str = "\x1b[0m";
+
+/// Candidates for the text from `start` to the cursor.
+#[derive(Default)]
+pub struct Completion {
+    pub start: usize,
+    pub candidates: Vec<Candidate>,
+}
+
+pub struct Candidate {
+    /// What replaces the text being completed. A single candidate is
+    /// followed by a space unless it ends with `/`.
+    pub text: String,
+    /// How it is shown in the list of candidates.
+    pub display: String,
+}
+
+/// What stays from one line to the next: the history to go through and
+/// the text cut last.
+#[derive(Default)]
+pub struct Editor {
+    history: Vec<String>,
+    cut: String,
+}
+
+/// The line being edited.
+struct Line<'p> {
+    helper: &'p dyn Helper,
+    buffer: String,
+    /// Byte offset in `buffer`, always on a character boundary.
+    cursor: usize,
+    prompt: &'p str,
+    continuation: &'p str,
+    /// Row of the cursor counted from the prompt, where the next redraw
+    /// has to go back to.
+    row: usize,
+    /// Position in the history, `history.len()` for the new line.
+    history_index: usize,
+    /// The new line while going through the history.
+    saved: String,
+    /// The rest of the history entry the line is the start of.
+    suggestion: String,
+    /// Bytes drawn as matched by the search.
+    matches: Vec<usize>,
+    /// A line drawn under the buffer, the state of the search.
+    footer: String,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `line` the most recent history entry, unless it is the same
+    /// as the last one.
+    pub fn add_history(&mut self, line: &str) {
+        if !line.trim().is_empty() && self.history.last().is_none_or(|last| last != line) {
+            self.history.push(line.to_string());
+        }
+    }
+
+    /// Reads one command, with `prompt` in front of its first line and
+    /// `continuation` in front of the others.
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        continuation: &str,
+        helper: &dyn Helper,
+    ) -> io::Result<Input> {
+        if !is_tty() {
+            return read_plain(prompt, continuation, helper);
+        }
+        let _raw = RawMode::enable()?;
+        let mut line = Line {
+            helper,
+            buffer: String::new(),
+            cursor: 0,
+            prompt,
+            continuation,
+            row: 0,
+            history_index: self.history.len(),
+            saved: String::new(),
+            suggestion: String::new(),
+            matches: Vec::new(),
+            footer: String::new(),
+        };
+        line.refresh()?;
+        // the key that ended a search, handled as if just typed
+        let mut pending = None;
+        loop {
+            let key = match pending.take() {
+                Some(key) => key,
+                None => match read_key()? {
+                    Some(key) => key,
+                    None => return Ok(Input::Eof),
+                },
+            };
+            match key {
+                Key::Enter if helper.incomplete(&line.buffer) => {
+                    line.cursor = line.buffer.len();
+                    line.insert("\n");
+                }
+                Key::Enter => {
+                    line.finish("")?;
+                    return Ok(Input::Line(line.buffer));
+                }
+                Key::Ctrl('c') => {
+                    line.finish("^C")?;
+                    return Ok(Input::Interrupted);
+                }
+                Key::Ctrl('d') if line.buffer.is_empty() => {
+                    line.finish("")?;
+                    return Ok(Input::Eof);
+                }
+                Key::Right | Key::Ctrl('f') | Key::End
+                    if line.cursor == line.buffer.len() && !line.suggestion.is_empty() =>
+                {
+                    let suggestion = std::mem::take(&mut line.suggestion);
+                    line.insert(&suggestion);
+                }
+                Key::Tab => complete(&mut line)?,
+                Key::Ctrl('r') => pending = search::search(&mut line)?,
+                Key::Ctrl('l') => {
+                    print!("\x1b[H\x1b[2J");
+                    line.row = 0;
+                }
+                key => self.edit(&mut line, key),
+            }
+            line.suggestion = self.suggest(&line);
+            line.refresh()?;
+        }
+    }
+
+    fn edit(&mut self, line: &mut Line, key: Key) {
+        match key {
+            Key::Char(ch) => {
+                let mut text = [0; 4];
+                line.insert(ch.encode_utf8(&mut text));
+            }
+            Key::Ctrl('a') | Key::Home => line.cursor = line.line_start(),
+            Key::Ctrl('e') | Key::End => line.cursor = line.line_end(),
+            Key::Ctrl('b') | Key::Left => line.cursor = line.prev(line.cursor),
+            Key::Ctrl('f') | Key::Right => line.cursor = line.next(line.cursor),
+            Key::Alt('b') | Key::WordLeft => line.cursor = line.word_start(),
+            Key::Alt('f') | Key::WordRight => line.cursor = line.word_end(),
+            Key::Backspace => {
+                let start = line.prev(line.cursor);
+                line.remove(start, line.cursor);
+            }
+            Key::Ctrl('d') | Key::Delete => {
+                let end = line.next(line.cursor);
+                line.remove(line.cursor, end);
+            }
+            Key::Ctrl('w') | Key::Alt('\x7f') => {
+                let start = line.blank_word_start();
+                self.cut = line.remove(start, line.cursor);
+            }
+            Key::Alt('d') => {
+                let end = line.word_end();
+                self.cut = line.remove(line.cursor, end);
+            }
+            Key::Ctrl('u') => {
+                let start = line.line_start();
+                self.cut = line.remove(start, line.cursor);
+            }
+            Key::Ctrl('k') => {
+                let end = line.line_end();
+                self.cut = line.remove(line.cursor, end);
+            }
+            Key::Ctrl('y') => line.insert(&self.cut.clone()),
+            Key::Ctrl('t') => line.transpose(),
+            // inside a command of several lines these move between its
+            // lines first, the history only comes after the first (last) one
+            Key::Ctrl('p') | Key::Up if !line.vertical(true) => {
+                self.go_through_history(line, true);
+            }
+            Key::Ctrl('n') | Key::Down if !line.vertical(false) => {
+                self.go_through_history(line, false);
+            }
+            _ => {}
+        }
+    }
+
+    /// What the most recent history entry starting with the line adds to
+    /// it, while the cursor is at the end.
+    fn suggest(&self, line: &Line) -> String {
+        if line.buffer.trim().is_empty() || line.cursor != line.buffer.len() {
+            return String::new();
+        }
+        self.history
+            .iter()
+            .rev()
+            .find_map(|entry| entry.strip_prefix(line.buffer.as_str()))
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Replaces the line with the previous (or next) history entry.
+    fn go_through_history(&self, line: &mut Line, back: bool) {
+        let index = if back {
+            match line.history_index.checked_sub(1) {
+                Some(index) => index,
+                None => return,
+            }
+        } else if line.history_index < self.history.len() {
+            line.history_index + 1
+        } else {
+            return;
+        };
+        if line.history_index == self.history.len() {
+            line.saved = std::mem::take(&mut line.buffer);
+        }
+        line.history_index = index;
+        line.buffer = match self.history.get(index) {
+            Some(entry) => entry.clone(),
+            None => std::mem::take(&mut line.saved),
+        };
+        line.cursor = line.buffer.len();
+    }
+}
+
+/// Tab: a single candidate replaces the word, several are completed as far
+/// as they agree and listed under the line when that doesn't add anything.
+fn complete(line: &mut Line) -> io::Result<()> {
+    let Completion { start, candidates } = line.helper.complete(&line.buffer, line.cursor);
+    let typed = &line.buffer[start..line.cursor];
+    match candidates.as_slice() {
+        [] => print!("\x07"),
+        [only] => {
+            let mut text = only.text.clone();
+            if !text.ends_with('/') {
+                text.push(' ');
+            }
+            line.remove(start, line.cursor);
+            line.insert(&text);
+        }
+        [first, rest @ ..] => {
+            let mut common = first.text.as_str();
+            for candidate in rest {
+                let len = common
+                    .char_indices()
+                    .zip(candidate.text.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(common.len().min(candidate.text.len()), |((i, _), _)| i);
+                common = &common[..len];
+            }
+            if common.len() > typed.len() {
+                let common = common.to_string();
+                line.remove(start, line.cursor);
+                line.insert(&common);
+            } else {
+                list(line, &candidates)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints the candidates in columns under the command, like `ls` does, and
+/// starts the prompt again under them.
+fn list(line: &mut Line, candidates: &[Candidate]) -> io::Result<()> {
+    let cursor = line.cursor;
+    line.finish("")?;
+    line.cursor = cursor;
+    let width = candidates
+        .iter()
+        .map(|candidate| str_width(&candidate.display))
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let per_row = (columns() / width).max(1);
+    let rows = candidates.len().div_ceil(per_row);
+    let mut out = String::new();
+    for row in 0..rows {
+        for candidate in candidates.iter().skip(row).step_by(rows) {
+            out.push_str(&candidate.display);
+            out.push_str(&" ".repeat(width - str_width(&candidate.display)));
+        }
+        out.push_str("\r\n");
+    }
+    line.row = 0;
+    let mut stdout = stdout().lock();
+    stdout.write_all(out.as_bytes())?;
+    stdout.flush()
+}
+
+/// The same without a terminal: the prompts and whole lines as they come.
+fn read_plain(prompt: &str, continuation: &str, helper: &dyn Helper) -> io::Result<Input> {
+    print!("{}", prompt);
+    stdout().flush()?;
+    let mut buffer = String::new();
+    if stdin().lock().read_line(&mut buffer)? == 0 {
+        println!();
+        return Ok(Input::Eof);
+    }
+    while helper.incomplete(buffer.trim_end_matches('\n')) {
+        print!("{}", continuation);
+        stdout().flush()?;
+        if stdin().lock().read_line(&mut buffer)? == 0 {
+            break;
+        }
+    }
+    Ok(Input::Line(buffer.trim_end_matches('\n').to_string()))
+}
+
+impl Line<'_> {
+    fn insert(&mut self, text: &str) {
+        self.buffer.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Takes `start..end` out of the buffer, the cursor goes to `start`.
+    fn remove(&mut self, start: usize, end: usize) -> String {
+        self.cursor = start;
+        self.buffer.drain(start..end).collect()
+    }
+
+    /// The character boundary before `at`, combining marks go with the
+    /// character they are drawn on.
+    fn prev(&self, at: usize) -> usize {
+        let mut chars = self.buffer[..at].char_indices().rev();
+        let mut at = at;
+        for (i, ch) in chars.by_ref() {
+            at = i;
+            if !is_combining(ch) {
+                break;
+            }
+        }
+        at
+    }
+
+    fn next(&self, at: usize) -> usize {
+        let mut chars = self.buffer[at..].char_indices();
+        if chars.next().is_none() {
+            return at;
+        }
+        match chars.find(|&(_, ch)| !is_combining(ch)) {
+            Some((i, _)) => at + i,
+            None => self.buffer.len(),
+        }
+    }
+
+    fn line_start(&self) -> usize {
+        self.buffer[..self.cursor].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    fn line_end(&self) -> usize {
+        self.buffer[self.cursor..]
+            .find('\n')
+            .map_or(self.buffer.len(), |i| self.cursor + i)
+    }
+
+    /// Start of the word before the cursor, words being letters, digits
+    /// and `_`.
+    fn word_start(&self) -> usize {
+        let before = &self.buffer[..self.cursor];
+        let end = before.trim_end_matches(|c: char| !is_word(c)).len();
+        before[..end].trim_end_matches(is_word).len()
+    }
+
+    fn word_end(&self) -> usize {
+        let after = &self.buffer[self.cursor..];
+        let start = after.len() - after.trim_start_matches(|c: char| !is_word(c)).len();
+        let rest = &after[start..];
+        self.cursor + start + (rest.len() - rest.trim_start_matches(is_word).len())
+    }
+
+    /// Start of the word before the cursor for Ctrl-W: everything up to a
+    /// blank.
+    fn blank_word_start(&self) -> usize {
+        let before = &self.buffer[..self.cursor];
+        before
+            .trim_end_matches([' ', '\t'])
+            .trim_end_matches(|c: char| !c.is_whitespace())
+            .len()
+    }
+
+    fn transpose(&mut self) {
+        if self.cursor == 0 {
+            return;
+        }
+        // at the end of the line the last two characters are swapped
+        let at = if self.cursor == self.buffer.len() {
+            self.prev(self.cursor)
+        } else {
+            self.cursor
+        };
+        let start = self.prev(at);
+        let end = self.next(at);
+        if start == at {
+            return;
+        }
+        let first = self.buffer[start..at].to_string();
+        let second = self.buffer[at..end].to_string();
+        self.buffer.replace_range(start..end, &(second + &first));
+        self.cursor = end;
+    }
+
+    /// Up and down inside a command of several lines, keeping the column.
+    /// False on the first (or last) line.
+    fn vertical(&mut self, up: bool) -> bool {
+        let start = self.line_start();
+        let column = self.buffer[start..self.cursor].chars().count();
+        let target = if up {
+            if start == 0 {
+                return false;
+            }
+            self.buffer[..start - 1].rfind('\n').map_or(0, |i| i + 1)
+        } else {
+            match self.buffer[self.cursor..].find('\n') {
+                Some(i) => self.cursor + i + 1,
+                None => return false,
+            }
+        };
+        let line = &self.buffer[target..];
+        let line = &line[..line.find('\n').unwrap_or(line.len())];
+        self.cursor = target
+            + line
+                .char_indices()
+                .nth(column)
+                .map_or(line.len(), |(i, _)| i);
+        true
+    }
+
+    /// Draws the prompt and the buffer again, from the row the prompt
+    /// starts on, and puts the cursor back where it belongs.
+    fn refresh(&mut self) -> io::Result<()> {
+        let mut out = String::from("\r");
+        if self.row > 0 {
+            out.push_str(&format!("\x1b[{}A", self.row));
+        }
+        out.push_str("\x1b[J");
+        let mut layout = Layout::new(columns());
+        out.push_str(self.prompt);
+        layout.advance(self.prompt);
+        let mut styles = vec![None; self.buffer.len()];
+        for span in self.helper.highlight(&self.buffer) {
+            let end = span.end.min(styles.len());
+            styles[span.start.min(end)..end].fill(Some(span.style));
+        }
+        for &i in &self.matches {
+            if let Some(style) = styles.get_mut(i) {
+                *style = Some(MATCH);
+            }
+        }
+        let suggestion = self
+            .suggestion
+            .char_indices()
+            .map(|(i, ch)| (self.buffer.len() + i, ch, Some(SUGGESTION)));
+        let mut cursor = None;
+        let mut current = None;
+        for (i, ch, style) in self
+            .buffer
+            .char_indices()
+            .map(|(i, ch)| (i, ch, styles[i]))
+            .chain(suggestion)
+        {
+            if i == self.cursor && cursor.is_none() {
+                cursor = Some(layout.cursor());
+            }
+            if ch == '\n' {
+                // the continuation prompt has its own colors
+                if current.take().is_some() {
+                    out.push_str(RESET);
+                }
+                out.push_str("\r\n");
+                layout.put('\n');
+                out.push_str(self.continuation);
+                layout.advance(self.continuation);
+                continue;
+            }
+            if style != current {
+                if current.is_some() {
+                    out.push_str(RESET);
+                }
+                out.push_str(style.unwrap_or_default());
+                current = style;
+            }
+            out.push(ch);
+            layout.put(ch);
+        }
+        if current.is_some() {
+            out.push_str(RESET);
+        }
+        if cursor.is_none() {
+            cursor = Some(layout.cursor());
+        }
+        if !self.footer.is_empty() {
+            out.push_str("\r\n");
+            layout.put('\n');
+            out.push_str(&self.footer);
+            layout.advance(&self.footer);
+        }
+        let end = layout.cursor();
+        if end.1 == 0 && layout.col > 0 {
+            // the last line is full, the terminal waits before going on
+            out.push_str("\r\n");
+        }
+        let (row, col) = cursor.unwrap_or(end);
+        if end.0 > row {
+            out.push_str(&format!("\x1b[{}A", end.0 - row));
+        }
+        out.push('\r');
+        if col > 0 {
+            out.push_str(&format!("\x1b[{}C", col));
+        }
+        self.row = row;
+        let mut stdout = stdout().lock();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+
+    /// Shows the whole command one last time and leaves the cursor on the
+    /// next line, after `mark`.
+    fn finish(&mut self, mark: &str) -> io::Result<()> {
+        self.cursor = self.buffer.len();
+        self.suggestion.clear();
+        self.refresh()?;
+        print!("{}\r\n", mark);
+        stdout().flush()
+    }
+}
+
+fn is_word(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
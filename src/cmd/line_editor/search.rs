@@ -0,0 +1,101 @@
+//! Ctrl-R: what is typed is looked for in the history, and the best hit
+//! takes the place of the line, with where and how it ran under it.
+//!
+//! | keys | does |
+//! |---|---|
+//! | Ctrl-R / Up, Ctrl-S / Down | next / previous hit |
+//! | Ctrl-T | only the commands run in the current directory, or all again |
+//! | Ctrl-G | back to the line as it was |
+//!
+//! Any other key ends the search with the hit in the line and does what it
+//! always does, so Enter runs it.
+
+use std::io::{self, Write, stdout};
+
+use super::{
+    Hit, Line, RESET, SUGGESTION,
+    terminal::{Key, read_key},
+};
+
+/// The key that ended the search, `None` when there is none to handle.
+pub(super) fn search(line: &mut Line) -> io::Result<Option<Key>> {
+    let saved = (line.buffer.clone(), line.cursor);
+    line.suggestion.clear();
+    let mut query = String::new();
+    let mut here = false;
+    let mut hits = line.helper.search(&query, here);
+    let mut index = 0;
+    let key = loop {
+        show(line, &query, here, &hits, index);
+        line.refresh()?;
+        let Some(key) = read_key()? else {
+            break None;
+        };
+        match key {
+            Key::Char(ch) => query.push(ch),
+            Key::Backspace => {
+                query.pop();
+            }
+            Key::Ctrl('t') => here = !here,
+            Key::Ctrl('r') | Key::Up => {
+                if index + 1 < hits.len() {
+                    index += 1;
+                } else {
+                    bell()?;
+                }
+                continue;
+            }
+            Key::Ctrl('s') | Key::Down => {
+                if index > 0 {
+                    index -= 1;
+                } else {
+                    bell()?;
+                }
+                continue;
+            }
+            Key::Ctrl('g') => {
+                (line.buffer, line.cursor) = saved;
+                break None;
+            }
+            key => break Some(key),
+        }
+        hits = line.helper.search(&query, here);
+        index = 0;
+    };
+    line.matches.clear();
+    line.footer.clear();
+    Ok(key)
+}
+
+/// Puts the hit in the line and the state of the search under it. Without
+/// a hit the line keeps the last one.
+fn show(line: &mut Line, query: &str, here: bool, hits: &[Hit], index: usize) {
+    let scope = if here { "search here" } else { "search" };
+    match hits.get(index) {
+        Some(hit) => {
+            line.buffer = hit.text.clone();
+            line.cursor = line.buffer.len();
+            line.matches = hit.matches.clone();
+            line.footer = format!(
+                "{}: {}  {}{}/{}  {}{}",
+                scope,
+                query,
+                SUGGESTION,
+                index + 1,
+                hits.len(),
+                hit.detail,
+                RESET
+            );
+        }
+        None => {
+            line.matches.clear();
+            line.footer = format!("{}: {}  {}no match{}", scope, query, SUGGESTION, RESET);
+        }
+    }
+}
+
+fn bell() -> io::Result<()> {
+    let mut stdout = stdout().lock();
+    stdout.write_all(b"\x07")?;
+    stdout.flush()
+}
@@ -0,0 +1,164 @@
+use std::{
+    io::{self, Read},
+    mem,
+};
+
+/// Puts the terminal in raw mode (keys come one by one, nothing is echoed,
+/// Ctrl-C is just a byte) until dropped.
+pub struct RawMode {
+    saved: libc::termios,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<Self> {
+        // SAFETY: termios is plain data that tcgetattr fills in
+        let mut saved: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = saved;
+        raw.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+        raw.c_cflag |= libc::CS8;
+        raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: raw is a valid termios copied from the current one
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores what tcgetattr gave us
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.saved) };
+    }
+}
+
+/// Both ends are a terminal, so there is someone to edit the line.
+pub fn is_tty() -> bool {
+    // SAFETY: isatty only looks at the descriptor
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+/// Width of the terminal, 80 if it can't tell.
+pub fn columns() -> usize {
+    // SAFETY: winsize is plain data that the ioctl fills in
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+        && size.ws_col > 0
+    {
+        size.ws_col as usize
+    } else {
+        80
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Key {
+    Char(char),
+    /// Ctrl and a letter, as the lowercase letter.
+    Ctrl(char),
+    /// Alt (or Esc first) and a character.
+    Alt(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    Unknown,
+}
+
+fn byte() -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match io::stdin().read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            // a signal the shell handles, the key still comes
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads the rest of a UTF-8 character that starts with `first`.
+fn utf8(first: u8) -> io::Result<char> {
+    let len = match first {
+        0xF0.. => 4,
+        0xE0.. => 3,
+        0xC0.. => 2,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match byte()? {
+            Some(byte) => bytes.push(byte),
+            None => break,
+        }
+    }
+    Ok(String::from_utf8_lossy(&bytes)
+        .chars()
+        .next()
+        .unwrap_or('\u{FFFD}'))
+}
+
+/// `ESC [ params final` or `ESC O final`.
+fn sequence() -> io::Result<Key> {
+    let Some(kind) = byte()? else {
+        return Ok(Key::Unknown);
+    };
+    if kind != b'[' && kind != b'O' {
+        return Ok(match kind {
+            b'\r' => Key::Alt('\r'),
+            0x7f => Key::Alt('\x7f'),
+            _ => Key::Alt(utf8(kind)?),
+        });
+    }
+    let mut params = String::new();
+    let last = loop {
+        match byte()? {
+            Some(byte @ 0x40..=0x7e) => break byte,
+            Some(byte) => params.push(byte as char),
+            None => return Ok(Key::Unknown),
+        }
+    };
+    let ctrl = params.ends_with(";5") || params.ends_with(";3");
+    Ok(match (last, params.as_str()) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) if ctrl => Key::WordRight,
+        (b'D', _) if ctrl => Key::WordLeft,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) | (b'~', "1" | "7") => Key::Home,
+        (b'F', _) | (b'~', "4" | "8") => Key::End,
+        (b'~', "3") => Key::Delete,
+        _ => Key::Unknown,
+    })
+}
+
+/// Waits for the next key, `None` when the input is closed.
+pub fn read_key() -> io::Result<Option<Key>> {
+    let Some(first) = byte()? else {
+        return Ok(None);
+    };
+    Ok(Some(match first {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => sequence()?,
+        1..=26 => Key::Ctrl((b'a' + first - 1) as char),
+        0..=31 => Key::Unknown,
+        _ => Key::Char(utf8(first)?),
+    }))
+}
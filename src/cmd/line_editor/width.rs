@@ -0,0 +1,164 @@
+/// Characters that take no room: combining marks, zero width spaces and
+/// joiners, variation selectors.
+const ZERO_WIDTH: &[(u32, u32)] = &[
+    (0x0300, 0x036F),
+    (0x0483, 0x0489),
+    (0x0591, 0x05BD),
+    (0x0610, 0x061A),
+    (0x064B, 0x065F),
+    (0x0E31, 0x0E31),
+    (0x0E34, 0x0E3A),
+    (0x1AB0, 0x1AFF),
+    (0x1DC0, 0x1DFF),
+    (0x200B, 0x200F),
+    (0x20D0, 0x20FF),
+    (0xFE00, 0xFE0F),
+    (0xFE20, 0xFE2F),
+    (0xE0100, 0xE01EF),
+];
+
+/// East Asian wide characters and emoji, two columns each.
+const WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115F),
+    (0x231A, 0x231B),
+    (0x2329, 0x232A),
+    (0x23E9, 0x23EC),
+    (0x25FD, 0x25FE),
+    (0x2614, 0x2615),
+    (0x2648, 0x2653),
+    (0x26A1, 0x26A1),
+    (0x26AA, 0x26AB),
+    (0x26BD, 0x26BE),
+    (0x26C4, 0x26C5),
+    (0x26D4, 0x26D4),
+    (0x26EA, 0x26EA),
+    (0x26F2, 0x26F5),
+    (0x26FA, 0x26FD),
+    (0x2705, 0x2705),
+    (0x270A, 0x270B),
+    (0x2728, 0x2728),
+    (0x274C, 0x274C),
+    (0x2753, 0x2757),
+    (0x2795, 0x2797),
+    (0x2B1B, 0x2B1C),
+    (0x2B50, 0x2B55),
+    (0x2E80, 0x303E),
+    (0x3041, 0x33FF),
+    (0x3400, 0x4DBF),
+    (0x4E00, 0x9FFF),
+    (0xA000, 0xA4CF),
+    (0xA960, 0xA97F),
+    (0xAC00, 0xD7A3),
+    (0xF900, 0xFAFF),
+    (0xFE10, 0xFE19),
+    (0xFE30, 0xFE6F),
+    (0xFF00, 0xFF60),
+    (0xFFE0, 0xFFE6),
+    (0x1F004, 0x1F004),
+    (0x1F0CF, 0x1F0CF),
+    (0x1F18E, 0x1F18E),
+    (0x1F191, 0x1F19A),
+    (0x1F200, 0x1F251),
+    (0x1F300, 0x1F64F),
+    (0x1F680, 0x1F6FF),
+    (0x1F900, 0x1F9FF),
+    (0x1FA70, 0x1FAFF),
+    (0x20000, 0x3FFFD),
+];
+
+fn in_table(table: &[(u32, u32)], ch: char) -> bool {
+    let ch = ch as u32;
+    table
+        .binary_search_by(|&(low, high)| {
+            if high < ch {
+                std::cmp::Ordering::Less
+            } else if low > ch {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// Columns a character takes on the terminal.
+pub fn char_width(ch: char) -> usize {
+    if ch.is_control() || in_table(ZERO_WIDTH, ch) {
+        0
+    } else if in_table(WIDE, ch) {
+        2
+    } else {
+        1
+    }
+}
+
+pub fn str_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// Whether the character is drawn over the one before it, so the cursor
+/// never stops between them.
+pub fn is_combining(ch: char) -> bool {
+    in_table(ZERO_WIDTH, ch)
+}
+
+/// Where text ends up on a terminal `cols` columns wide, with the lines it
+/// wraps into. Escape sequences (colors) take no room.
+#[derive(Clone, Copy)]
+pub struct Layout {
+    pub cols: usize,
+    pub row: usize,
+    pub col: usize,
+}
+
+impl Layout {
+    pub fn new(cols: usize) -> Self {
+        Self {
+            cols: cols.max(1),
+            row: 0,
+            col: 0,
+        }
+    }
+
+    pub fn put(&mut self, ch: char) {
+        if ch == '\n' {
+            self.row += 1;
+            self.col = 0;
+            return;
+        }
+        let width = char_width(ch);
+        if self.col + width > self.cols {
+            self.row += 1;
+            self.col = 0;
+        }
+        self.col += width;
+    }
+
+    pub fn advance(&mut self, text: &str) {
+        let mut chars = text.chars();
+        while let Some(ch) = chars.next() {
+            if ch == '\x1b' {
+                // `ESC [ ... final`, the final byte is in `@..=~`
+                if chars.next() == Some('[') {
+                    for ch in chars.by_ref() {
+                        if ('@'..='~').contains(&ch) {
+                            break;
+                        }
+                    }
+                }
+                continue;
+            }
+            self.put(ch);
+        }
+    }
+
+    /// Where the cursor really is: a full line leaves it at the start of
+    /// the next one.
+    pub fn cursor(&self) -> (usize, usize) {
+        if self.col >= self.cols {
+            (self.row + 1, 0)
+        } else {
+            (self.row, self.col)
+        }
+    }
+}
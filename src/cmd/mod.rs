@@ -1,3 +1,5 @@
 pub mod command_build;
 pub mod command_list;
 pub mod command_work;
+pub mod line_editor;
+pub mod shell;
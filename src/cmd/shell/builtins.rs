@@ -0,0 +1,860 @@
+use std::{io::Write, mem, path::Path};
+
+use super::{
+    Flow, Options, Shell, Var, history,
+    jobs::{State, give_terminal},
+    rc,
+    signals::{self, SIGNALS, signal_name, signal_number},
+};
+use crate::command_build::{
+    command::{FAILURE, INTERRUPTED, NOT_FOUND, SUCCESS, Status, USAGE, cancelled},
+    expand::is_name,
+    parse::CommandBackPack,
+};
+
+/// Commands that have to change the shell itself, so they can't be a
+/// separate `Command` or process.
+pub type Builtin = fn(&mut Shell, &[&str], &mut CommandBackPack) -> Status;
+
+/// Every name `lookup` knows.
+pub const NAMES: [&str; 26] = [
+    "cd", "pwd", "exit", "history", "clearHIS", "clear", "export", "unset", "set", "source", ".",
+    "break", "continue", "local", "return", "alias", "unalias", "trust", "untrust", "jobs", "fg",
+    "bg", "wait", "disown", "kill", "trap",
+];
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    match name {
+        "cd" => Some(cd),
+        "pwd" => Some(pwd),
+        "exit" => Some(exit),
+        "history" => Some(history),
+        "clearHIS" => Some(clear_history),
+        "clear" => Some(clear),
+        "export" => Some(export),
+        "unset" => Some(unset),
+        "set" => Some(set),
+        "source" | "." => Some(source),
+        "break" => Some(break_loop),
+        "continue" => Some(continue_loop),
+        "local" => Some(local),
+        "return" => Some(return_from),
+        "alias" => Some(alias),
+        "unalias" => Some(unalias),
+        "trust" => Some(trust),
+        "untrust" => Some(untrust),
+        "jobs" => Some(jobs),
+        "fg" => Some(fg),
+        "bg" => Some(bg),
+        "wait" => Some(wait),
+        "disown" => Some(disown),
+        "kill" => Some(kill),
+        "trap" => Some(trap),
+        _ => None,
+    }
+}
+
+fn cd(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    let new_dir = match args.first() {
+        Some(new_dir) => shell.now_dir.join(new_dir),
+        None => match shell.home() {
+            Some(home) => home,
+            None => {
+                let _ = writeln!(output.stderr, "shu: cd: no home directory");
+                return FAILURE;
+            }
+        },
+    };
+    match new_dir.canonicalize() {
+        Ok(abs) if abs.is_dir() => {
+            let moved = abs != shell.now_dir;
+            shell.now_dir = abs;
+            if moved && shell.options.cdrc {
+                rc::directory_rc(shell);
+            }
+            SUCCESS
+        }
+        Ok(abs) => {
+            let _ = writeln!(output.stderr, "shu: cd: not a directory: {}", abs.display());
+            FAILURE
+        }
+        Err(e) => {
+            let _ = writeln!(output.stderr, "shu: cd: {}", e);
+            FAILURE
+        }
+    }
+}
+
+fn pwd(shell: &mut Shell, _args: &[&str], output: &mut CommandBackPack) -> Status {
+    match writeln!(output.stdout, "{}", shell.now_dir.display()) {
+        Ok(()) => SUCCESS,
+        Err(_) => FAILURE,
+    }
+}
+
+fn exit(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    let code = match args.first() {
+        Some(code) => match code.parse() {
+            Ok(code) => code,
+            Err(_) => {
+                let _ = writeln!(
+                    output.stderr,
+                    "shu: exit: numeric argument required: {}",
+                    code
+                );
+                USAGE
+            }
+        },
+        None => shell.last_status,
+    };
+    shell.exit = Some(code);
+    code
+}
+
+/// `history [-n N] [-d | --cwd DIR] [-f | -s STATUS] [--since AGE] [-l]
+/// [WORD...]`: the numbered history, only the entries run in the current
+/// directory (or DIR), that failed (or ended with STATUS), in the last AGE
+/// (`30s`, `10m`, `2h`, `7d`) or whose command contains every WORD. `-n`
+/// keeps the last N of them, `-l` also shows when, how long, with what
+/// status and where each one ran.
+fn history(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    let (filter, last, long) = match history_args(shell, args) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            let _ = writeln!(output.stderr, "shu: history: {}", message);
+            return USAGE;
+        }
+    };
+    let entries = match history::load(&shell.history) {
+        Ok(entries) => entries,
+        Err(e) => {
+            let _ = writeln!(output.stderr, "shu: history file error: {}", e);
+            return FAILURE;
+        }
+    };
+    let found: Vec<_> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| filter.matches(entry))
+        .collect();
+    let skip = found.len() - last.unwrap_or(found.len()).min(found.len());
+    for (index, entry) in &found[skip..] {
+        // the following lines of a command line up under its first one
+        let command = entry.command.replace('\n', "\n       ");
+        let written = if long {
+            let time = match entry.time {
+                0 => "-".to_string(),
+                time => history::format_time(time),
+            };
+            writeln!(
+                output.stdout,
+                "{:>5}  {}  {:>7}  {:>3}  {}  {}",
+                index + 1,
+                time,
+                history::format_duration(entry.duration),
+                entry.status,
+                entry.cwd.display(),
+                command
+            )
+        } else {
+            writeln!(output.stdout, "{:>5}  {}", index + 1, command)
+        };
+        if written.is_err() {
+            return FAILURE;
+        }
+    }
+    SUCCESS
+}
+
+/// The filter, how many entries to keep and whether to show them in long
+/// form.
+fn history_args(
+    shell: &Shell,
+    args: &[&str],
+) -> Result<(history::Filter, Option<usize>, bool), String> {
+    let mut filter = history::Filter::default();
+    let mut last = None;
+    let mut long = false;
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .copied()
+                .ok_or_else(|| format!("{} needs a value", arg))
+        };
+        let invalid = |value: &str| format!("invalid value for {}: {}", arg, value);
+        match arg {
+            "-n" => {
+                let value = value()?;
+                last = Some(value.parse().map_err(|_| invalid(value))?);
+            }
+            "-d" | "--here" => filter.cwd = Some(shell.now_dir.clone()),
+            "--cwd" => {
+                let dir = shell.now_dir.join(value()?);
+                filter.cwd = Some(
+                    dir.canonicalize()
+                        .map_err(|e| format!("{}: {}", dir.display(), e))?,
+                );
+            }
+            "-f" | "--failed" => filter.failed = true,
+            "-s" | "--status" => {
+                let value = value()?;
+                filter.status = Some(value.parse().map_err(|_| invalid(value))?);
+            }
+            "--since" => {
+                let value = value()?;
+                let age = history::parse_age(value).ok_or_else(|| invalid(value))?;
+                filter.since = Some(history::now().saturating_sub(age));
+            }
+            "-l" | "--long" => long = true,
+            word => filter.words.push(word.to_string()),
+        }
+    }
+    Ok((filter, last, long))
+}
+
+fn clear_history(shell: &mut Shell, _args: &[&str], output: &mut CommandBackPack) -> Status {
+    match history::clear(&shell.history) {
+        Ok(()) => SUCCESS,
+        Err(e) => {
+            let _ = writeln!(output.stderr, "shu: history file error: {}", e);
+            FAILURE
+        }
+    }
+}
+
+fn clear(_shell: &mut Shell, _args: &[&str], output: &mut CommandBackPack) -> Status {
+    match write!(output.stdout, "{}[2J{}[1;1H", 27 as char, 27 as char) {
+        Ok(()) => SUCCESS,
+        Err(_) => FAILURE,
+    }
+}
+
+fn export(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    if args.is_empty() {
+        let mut exported: Vec<_> = shell.environment();
+        exported.sort();
+        for (name, value) in exported {
+            if writeln!(output.stdout, "export {}=\"{}\"", name, value).is_err() {
+                return FAILURE;
+            }
+        }
+        return SUCCESS;
+    }
+    let mut code = SUCCESS;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (*arg, None),
+        };
+        if !is_name(name) {
+            let _ = writeln!(
+                output.stderr,
+                "shu: export: not a valid identifier: {}",
+                arg
+            );
+            code = FAILURE;
+            continue;
+        }
+        let var = shell.vars.entry(name.to_string()).or_insert(Var {
+            value: String::new(),
+            exported: true,
+        });
+        var.exported = true;
+        if let Some(value) = value {
+            var.value = value;
+        }
+    }
+    code
+}
+
+fn unset(shell: &mut Shell, args: &[&str], _output: &mut CommandBackPack) -> Status {
+    for name in args {
+        shell.vars.remove(*name);
+    }
+    SUCCESS
+}
+
+/// `set -o NAME` turns an option on, `set +o NAME` off, `set -o` lists them.
+/// `set -eux` (or `+eux`) does the same for the options with a letter.
+fn set(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    if args.is_empty() || args == ["-o"] {
+        for (name, on) in shell.options.named() {
+            let state = if *on { "on" } else { "off" };
+            if writeln!(output.stdout, "{}\t{}", name, state).is_err() {
+                return FAILURE;
+            }
+        }
+        return SUCCESS;
+    }
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let on = match arg.chars().next() {
+            Some('-') => true,
+            Some('+') => false,
+            _ => {
+                let _ = writeln!(output.stderr, "shu: set: unknown option: {}", arg);
+                return USAGE;
+            }
+        };
+        let names: Vec<&str> = if &arg[1..] == "o" {
+            match args.next() {
+                Some(name) => vec![name],
+                None => {
+                    let _ = writeln!(output.stderr, "shu: set: {} needs an option name", arg);
+                    return USAGE;
+                }
+            }
+        } else {
+            // `-eux`: one option by letter each
+            let letters = arg[1..].chars().map(|letter| {
+                Options::LETTERS
+                    .iter()
+                    .find(|(known, _)| *known == letter)
+                    .map(|(_, name)| *name)
+            });
+            match letters.collect::<Option<Vec<_>>>() {
+                Some(names) if !names.is_empty() => names,
+                _ => {
+                    let _ = writeln!(output.stderr, "shu: set: unknown option: {}", arg);
+                    return USAGE;
+                }
+            }
+        };
+        for name in names {
+            let option = shell.options.named().into_iter().find(|(n, _)| *n == name);
+            let Some((_, option)) = option else {
+                let _ = writeln!(output.stderr, "shu: set: unknown option name: {}", name);
+                return USAGE;
+            };
+            *option = on;
+        }
+    }
+    SUCCESS
+}
+
+/// `source FILE [ARGS]` (or `. FILE`) runs a script in this shell, so it can
+/// change variables and the directory. Extra arguments become `$1`... for
+/// the time of the script.
+fn source(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    let Some((file, params)) = args.split_first() else {
+        let _ = writeln!(output.stderr, "shu: source: filename argument required");
+        return USAGE;
+    };
+    let saved = (!params.is_empty()).then(|| {
+        let args = [shell.args[0].clone()]
+            .into_iter()
+            .chain(params.iter().map(|param| param.to_string()))
+            .collect();
+        mem::replace(&mut shell.args, args)
+    });
+    let code = match rc::source_file(shell, Path::new(file)) {
+        Ok(code) => code,
+        Err(e) => {
+            let _ = writeln!(output.stderr, "shu: source: {}: {}", file, e);
+            FAILURE
+        }
+    };
+    if let Some(saved) = saved {
+        shell.args = saved;
+    }
+    code
+}
+
+/// `break [N]` and `continue [N]`: leave or restart the N-th enclosing loop.
+fn loop_control(
+    shell: &mut Shell,
+    args: &[&str],
+    output: &mut CommandBackPack,
+    name: &str,
+    flow: fn(usize) -> Flow,
+) -> Status {
+    let count = match args.first().map(|count| count.parse::<usize>()) {
+        None => 1,
+        Some(Ok(count)) if count > 0 => count,
+        Some(_) => {
+            let _ = writeln!(output.stderr, "shu: {}: loop count out of range", name);
+            return FAILURE;
+        }
+    };
+    if shell.loops == 0 {
+        let _ = writeln!(output.stderr, "shu: {}: only meaningful in a loop", name);
+        return SUCCESS;
+    }
+    shell.flow = Some(flow(count.min(shell.loops)));
+    SUCCESS
+}
+
+fn break_loop(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    loop_control(shell, args, output, "break", Flow::Break)
+}
+
+fn continue_loop(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    loop_control(shell, args, output, "continue", Flow::Continue)
+}
+
+/// `local NAME[=value]...`: the variable gets its old value back when the
+/// function returns.
+fn local(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    let Some(frame) = shell.locals.last_mut() else {
+        let _ = writeln!(output.stderr, "shu: local: can only be used in a function");
+        return FAILURE;
+    };
+    let mut code = SUCCESS;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (*arg, None),
+        };
+        if !is_name(name) {
+            let _ = writeln!(output.stderr, "shu: local: not a valid identifier: {}", arg);
+            code = FAILURE;
+            continue;
+        }
+        frame
+            .entry(name.to_string())
+            .or_insert_with(|| shell.vars.get(name).cloned());
+        match value {
+            Some(value) => {
+                let var = Var {
+                    value,
+                    exported: false,
+                };
+                shell.vars.insert(name.to_string(), var);
+            }
+            None => {
+                shell.vars.remove(name);
+            }
+        }
+    }
+    code
+}
+
+/// `return [N]` leaves the running function or sourced file.
+fn return_from(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    if shell.depth == 0 {
+        let _ = writeln!(
+            output.stderr,
+            "shu: return: can only `return' from a function or sourced script"
+        );
+        return FAILURE;
+    }
+    let code = match args.first() {
+        Some(code) => match code.parse() {
+            Ok(code) => code,
+            Err(_) => {
+                let _ = writeln!(
+                    output.stderr,
+                    "shu: return: numeric argument required: {}",
+                    code
+                );
+                USAGE
+            }
+        },
+        None => shell.last_status,
+    };
+    shell.flow = Some(Flow::Return(code));
+    code
+}
+
+/// `'text'` with the single quotes inside escaped, so what `alias`, `trap`
+/// and `set -x` show can be read back (from a `.shurc` for example).
+pub fn single_quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// `alias` lists every alias, `alias NAME` shows one and
+/// `alias NAME=text` defines one.
+fn alias(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    if args.is_empty() {
+        let mut aliases: Vec<_> = shell.aliases.iter().collect();
+        aliases.sort();
+        for (name, text) in aliases {
+            if writeln!(output.stdout, "alias {}={}", name, single_quoted(text)).is_err() {
+                return FAILURE;
+            }
+        }
+        return SUCCESS;
+    }
+    let mut code = SUCCESS;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, text)) if !name.is_empty() && !name.contains([' ', '\t', '/']) => {
+                shell.aliases.insert(name.to_string(), text.to_string());
+            }
+            Some(_) => {
+                let _ = writeln!(output.stderr, "shu: alias: invalid alias name: {}", arg);
+                code = FAILURE;
+            }
+            None => match shell.aliases.get(*arg) {
+                Some(text) => {
+                    if writeln!(output.stdout, "alias {}={}", arg, single_quoted(text)).is_err() {
+                        return FAILURE;
+                    }
+                }
+                None => {
+                    let _ = writeln!(output.stderr, "shu: alias: {}: not found", arg);
+                    code = FAILURE;
+                }
+            },
+        }
+    }
+    code
+}
+
+/// `unalias NAME...`, or `unalias -a` to remove them all.
+fn unalias(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    if args == ["-a"] {
+        shell.aliases.clear();
+        return SUCCESS;
+    }
+    let mut code = SUCCESS;
+    for name in args {
+        if shell.aliases.remove(*name).is_none() {
+            let _ = writeln!(output.stderr, "shu: unalias: {}: not found", name);
+            code = FAILURE;
+        }
+    }
+    code
+}
+
+/// `trust [DIR]`: lets the `.shurc` of a directory (the current one by
+/// default) run when the shell starts or `cd`s there, until the file
+/// changes; without it a project rc is never read. `trust -l` lists the
+/// trusted directories.
+fn trust(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    if args == ["-l"] {
+        for dir in rc::trusted(shell) {
+            if writeln!(output.stdout, "{}", dir.display()).is_err() {
+                return FAILURE;
+            }
+        }
+        return SUCCESS;
+    }
+    set_trusted(shell, args, output, "trust", true)
+}
+
+/// `untrust [DIR]`: the opposite of `trust`.
+fn untrust(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    set_trusted(shell, args, output, "untrust", false)
+}
+
+fn set_trusted(
+    shell: &mut Shell,
+    args: &[&str],
+    output: &mut CommandBackPack,
+    name: &str,
+    trust: bool,
+) -> Status {
+    let dir = match args.first() {
+        Some(dir) => shell.now_dir.join(dir),
+        None => shell.now_dir.clone(),
+    };
+    let result = dir
+        .canonicalize()
+        .and_then(|dir| rc::set_trusted(shell, &dir, trust));
+    match result {
+        Ok(()) => SUCCESS,
+        Err(e) => {
+            let _ = writeln!(output.stderr, "shu: {}: {}: {}", name, dir.display(), e);
+            FAILURE
+        }
+    }
+}
+
+/// `jobs [-l | -p]`: every job with its state, `-l` with its process group
+/// too and `-p` only the process groups.
+fn jobs(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    let (long, groups) = match args {
+        [] => (false, false),
+        ["-l"] => (true, false),
+        ["-p"] => (false, true),
+        _ => {
+            let _ = writeln!(output.stderr, "shu: jobs: usage: jobs [-l | -p]");
+            return USAGE;
+        }
+    };
+    for (job, state) in shell.jobs.report(true) {
+        let line = match job.control.leader() {
+            _ if !groups => job.line(state, long),
+            Some(pid) => pid.to_string(),
+            None => continue,
+        };
+        if writeln!(output.stdout, "{}", line).is_err() {
+            return FAILURE;
+        }
+    }
+    SUCCESS
+}
+
+/// `fg [JOB]`: the job (the current one by default) goes on in the
+/// foreground, with the terminal, until it ends or stops again.
+fn fg(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    let job = match shell.jobs.find(args.first().copied()) {
+        Ok(job) => job,
+        Err(e) => {
+            let _ = writeln!(output.stderr, "shu: fg: {}", e);
+            return FAILURE;
+        }
+    };
+    let _ = writeln!(output.stdout, "{}", job.text);
+    let _ = output.stdout.flush();
+    job.control.set_foreground(true);
+    if let Some(tty) = shell.terminal {
+        give_terminal(tty, job.control.pgid());
+    }
+    let _ = job.control.resume();
+    let status = match shell.jobs.wait(job.id, true) {
+        Some(status) => status,
+        None => {
+            shell.jobs.shown(job.id);
+            let _ = writeln!(output.stdout, "\n{}", job.line(State::Stopped, false));
+            128 + libc::SIGTSTP
+        }
+    };
+    job.control.set_foreground(false);
+    if let Some(tty) = shell.terminal {
+        // SAFETY: getpgrp can't fail
+        give_terminal(tty, unsafe { libc::getpgrp() });
+    }
+    status
+}
+
+/// The jobs named by `args`, or the current one.
+fn specs<'a>(args: &[&'a str]) -> Vec<Option<&'a str>> {
+    if args.is_empty() {
+        vec![None]
+    } else {
+        args.iter().map(|arg| Some(*arg)).collect()
+    }
+}
+
+/// `bg [JOB...]`: stopped jobs (the current one by default) go on in the
+/// background.
+fn bg(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    let mut code = SUCCESS;
+    for spec in specs(args) {
+        match shell.jobs.find(spec) {
+            Ok(job) if job.control.state() != State::Stopped => {
+                let _ = writeln!(output.stderr, "shu: bg: job {} is not stopped", job.id);
+            }
+            Ok(job) => {
+                let _ = job.control.resume();
+                shell.jobs.shown(job.id);
+                let _ = writeln!(output.stdout, "[{}]{} {} &", job.id, job.mark, job.text);
+            }
+            Err(e) => {
+                let _ = writeln!(output.stderr, "shu: bg: {}", e);
+                code = FAILURE;
+            }
+        }
+    }
+    code
+}
+
+/// `wait [JOB | PID...]`: waits for jobs (every running one by default) to
+/// end; the status is the one of the last named. A PID is the one `jobs -p`
+/// shows for a job.
+fn wait(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    if args.is_empty() {
+        for job in shell.jobs.all() {
+            if job.control.state() != State::Stopped {
+                shell.jobs.wait(job.id, false);
+            }
+            if cancelled() {
+                return INTERRUPTED;
+            }
+        }
+        return SUCCESS;
+    }
+    let mut code = SUCCESS;
+    for arg in args {
+        let found = match arg.parse::<libc::pid_t>() {
+            Ok(pid) => shell
+                .jobs
+                .all()
+                .into_iter()
+                .find(|job| job.control.leader() == Some(pid))
+                .ok_or_else(|| format!("pid {} is not a child of this shell", pid)),
+            Err(_) => shell.jobs.find(Some(arg)),
+        };
+        code = match found {
+            Ok(job) => match shell.jobs.wait(job.id, false) {
+                Some(status) => status,
+                None if cancelled() => return INTERRUPTED,
+                None => 128 + libc::SIGTSTP,
+            },
+            Err(e) => {
+                let _ = writeln!(output.stderr, "shu: wait: {}", e);
+                NOT_FOUND
+            }
+        };
+    }
+    code
+}
+
+/// `disown [-a | JOB...]`: forgets jobs (the current one by default); they
+/// go on, but aren't listed, waited for or hung up anymore.
+fn disown(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    if args == ["-a"] {
+        for job in shell.jobs.all() {
+            shell.jobs.remove(job.id);
+        }
+        return SUCCESS;
+    }
+    let mut code = SUCCESS;
+    for spec in specs(args) {
+        match shell.jobs.find(spec) {
+            Ok(job) => shell.jobs.remove(job.id),
+            Err(e) => {
+                let _ = writeln!(output.stderr, "shu: disown: {}", e);
+                code = FAILURE;
+            }
+        }
+    }
+    code
+}
+
+/// `kill [-s SIGNAL | -SIGNAL] TARGET...`: sends a signal (`TERM` by
+/// default) to jobs (`%n`) and processes. `kill -l` lists the signals.
+fn kill(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    if args == ["-l"] {
+        return list_signals(output);
+    }
+    let (signal, targets) = match args {
+        ["-s", name, targets @ ..] => (*name, targets),
+        [flag, targets @ ..] if flag.len() > 1 && flag.starts_with('-') => (&flag[1..], targets),
+        targets => ("TERM", targets),
+    };
+    let Some(signal) = signal_number(signal) else {
+        let _ = writeln!(output.stderr, "shu: kill: {}: invalid signal", signal);
+        return USAGE;
+    };
+    if targets.is_empty() {
+        let _ = writeln!(
+            output.stderr,
+            "shu: kill: usage: kill [-s SIGNAL | -SIGNAL] %JOB | PID..."
+        );
+        return USAGE;
+    }
+    let mut code = SUCCESS;
+    for target in targets {
+        let sent = if target.starts_with('%') {
+            shell.jobs.find(Some(target)).and_then(|job| {
+                job.control
+                    .signal(signal)
+                    .map_err(|e| format!("{}: {}", target, e))?;
+                // a stopped job only sees the signal once it goes on
+                if job.control.state() == State::Stopped
+                    && matches!(signal, libc::SIGTERM | libc::SIGHUP | libc::SIGINT)
+                {
+                    let _ = job.control.signal(libc::SIGCONT);
+                }
+                Ok(())
+            })
+        } else {
+            match target.parse::<libc::pid_t>() {
+                // SAFETY: kill only sends a signal
+                Ok(pid) if unsafe { libc::kill(pid, signal) } == 0 => Ok(()),
+                Ok(_) => Err(format!("{}: {}", target, std::io::Error::last_os_error())),
+                Err(_) => Err(format!("{}: arguments must be process or job IDs", target)),
+            }
+        };
+        if let Err(e) = sent {
+            let _ = writeln!(output.stderr, "shu: kill: {}", e);
+            code = FAILURE;
+        }
+    }
+    code
+}
+
+/// `kill -l` and `trap -l`.
+fn list_signals(output: &mut CommandBackPack) -> Status {
+    for (name, number) in SIGNALS {
+        if writeln!(output.stdout, "{:>2}) {}", number, name).is_err() {
+            return FAILURE;
+        }
+    }
+    SUCCESS
+}
+
+/// What `trap` takes a condition for: `EXIT` (or `0`), `ERR` or a signal,
+/// as the name it is kept by and the signal.
+fn condition(name: &str) -> Option<(&'static str, Option<libc::c_int>)> {
+    match name.to_ascii_uppercase().as_str() {
+        "EXIT" | "0" => Some(("EXIT", None)),
+        "ERR" => Some(("ERR", None)),
+        _ => {
+            let signal = signal_number(name)?;
+            Some((signal_name(signal)?, Some(signal)))
+        }
+    }
+}
+
+/// `trap [-lp] [[COMMAND] CONDITION...]`: COMMAND runs when a signal
+/// arrives, when the shell exits (`EXIT`) or after a command fails where
+/// nothing tests it (`ERR`). An empty COMMAND ignores the signal, `-` (or
+/// none) puts back what was done before. Without a COMMAND, or with `-p`,
+/// the traps are shown.
+fn trap(shell: &mut Shell, args: &[&str], output: &mut CommandBackPack) -> Status {
+    let args = args.strip_prefix(&["--"]).unwrap_or(args);
+    let (command, names) = match args {
+        ["-l"] => return list_signals(output),
+        [] | ["-p"] => {
+            let mut traps: Vec<_> = shell.traps.iter().collect();
+            traps.sort();
+            for (name, command) in traps {
+                if writeln!(output.stdout, "trap -- {} {}", single_quoted(command), name).is_err() {
+                    return FAILURE;
+                }
+            }
+            return SUCCESS;
+        }
+        ["-p", names @ ..] => {
+            let mut code = SUCCESS;
+            for name in names {
+                match condition(name) {
+                    Some((name, _)) => {
+                        if let Some(command) = shell.traps.get(name) {
+                            let _ = writeln!(
+                                output.stdout,
+                                "trap -- {} {}",
+                                single_quoted(command),
+                                name
+                            );
+                        }
+                    }
+                    None => {
+                        let _ = writeln!(output.stderr, "shu: trap: {}: invalid condition", name);
+                        code = FAILURE;
+                    }
+                }
+            }
+            return code;
+        }
+        // only conditions: they are put back
+        [first, ..] if args.len() == 1 || first.parse::<u32>().is_ok() => (None, args),
+        ["-", names @ ..] => (None, names),
+        [command, names @ ..] => (Some(*command), names),
+    };
+    let mut code = SUCCESS;
+    for name in names {
+        let Some((name, signal)) = condition(name) else {
+            let _ = writeln!(output.stderr, "shu: trap: {}: invalid condition", name);
+            code = FAILURE;
+            continue;
+        };
+        if let Some(signal) = signal
+            && let Err(e) = signals::trap(signal, command)
+        {
+            let _ = writeln!(output.stderr, "shu: trap: {}: {}", name, e);
+            code = FAILURE;
+            continue;
+        }
+        match command {
+            Some(command) => shell.traps.insert(name.to_string(), command.to_string()),
+            None => shell.traps.remove(name),
+        };
+    }
+    code
+}
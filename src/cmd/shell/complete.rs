@@ -0,0 +1,225 @@
+//! What Tab offers for the word before the cursor: command names in
+//! command position, the options of a command after a `-`, `$VAR` names and
+//! otherwise paths, relative to the working directory of the shell.
+
+use std::{collections::BTreeSet, env, fs, os::unix::fs::PermissionsExt};
+
+use super::{Shell, builtins};
+use crate::command_build::expand::{Variables, is_name};
+use crate::command_work::{BUILTINS, builtin_flags};
+use crate::line_editor::{Candidate, Completion};
+
+/// Characters a completed word gets a backslash in front of.
+const SPECIAL: &str = " \t\"'\\$&;|<>()*?[]{}!#`";
+
+/// Words after which a new command starts.
+const LEADERS: [&str; 8] = ["if", "then", "elif", "else", "while", "until", "do", "!"];
+
+pub fn complete(shell: &Shell, before: &str) -> Completion {
+    let start = word_start(before);
+    let word = &before[start..];
+    if let Some(dollar) = word.rfind('$')
+        && let name = word[dollar + 1..].trim_start_matches('{')
+        && (name.is_empty() || is_name(name))
+    {
+        return Completion {
+            start: start + dollar,
+            candidates: variables(shell, name, word[dollar + 1..].starts_with('{')),
+        };
+    }
+    let words = command_words(&before[..start]);
+    let candidates = match words.first() {
+        None if !word.contains('/') => commands(shell, &unquote(word)),
+        Some(&command) if word.starts_with('-') || word.starts_with('+') => {
+            flags(command, &unquote(word))
+        }
+        Some(&"set") if matches!(words.last(), Some(&("-o" | "+o"))) => {
+            options(shell, &unquote(word))
+        }
+        command => paths(shell, word, command == Some(&"cd")),
+    };
+    Completion { start, candidates }
+}
+
+/// Where the word before the cursor starts: after the last blank or
+/// operator that isn't quoted.
+fn word_start(before: &str) -> usize {
+    let mut start = 0;
+    let mut quote = None;
+    let mut chars = before.char_indices();
+    while let Some((i, ch)) = chars.next() {
+        match (quote, ch) {
+            (None | Some('"'), '\\') => {
+                chars.next();
+            }
+            (None, '\'' | '"') => quote = Some(ch),
+            (Some(q), ch) if q == ch => quote = None,
+            (None, ' ' | '\t' | '\n' | ';' | '|' | '&' | '(' | ')' | '<' | '>') => {
+                start = i + ch.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    start
+}
+
+/// The words of the command the cursor is in, before the one being typed,
+/// without the assignments and keywords in front of the command name.
+fn command_words(before: &str) -> Vec<&str> {
+    let command = before
+        .rsplit(['\n', ';', '|', '&', '(', ')'])
+        .next()
+        .unwrap_or(before);
+    let mut words: Vec<&str> = command.split_whitespace().collect();
+    let skip = words
+        .iter()
+        .take_while(|word| {
+            LEADERS.contains(word) || word.split_once('=').is_some_and(|(name, _)| is_name(name))
+        })
+        .count();
+    words.drain(..skip);
+    words
+}
+
+fn unquote(word: &str) -> String {
+    let mut result = String::new();
+    let mut chars = word.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\'' | '"' => {}
+            '\\' => result.extend(chars.next()),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+fn escape(text: &str) -> String {
+    let mut result = String::new();
+    for ch in text.chars() {
+        if SPECIAL.contains(ch) {
+            result.push('\\');
+        }
+        result.push(ch);
+    }
+    result
+}
+
+fn candidates<'n>(names: impl IntoIterator<Item = &'n str>, prefix: &str) -> Vec<Candidate> {
+    names
+        .into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| Candidate {
+            text: escape(name),
+            display: name.to_string(),
+        })
+        .collect()
+}
+
+fn variables(shell: &Shell, prefix: &str, braces: bool) -> Vec<Candidate> {
+    let names: BTreeSet<&str> = shell.vars.keys().map(String::as_str).collect();
+    names
+        .into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| Candidate {
+            text: if braces {
+                format!("${{{}}}", name)
+            } else {
+                format!("${}", name)
+            },
+            display: name.to_string(),
+        })
+        .collect()
+}
+
+/// Builtins, functions, aliases and the executables in `$PATH`.
+fn commands(shell: &Shell, prefix: &str) -> Vec<Candidate> {
+    let mut names: BTreeSet<String> = builtins::NAMES
+        .iter()
+        .chain(&BUILTINS)
+        .map(|name| name.to_string())
+        .chain(shell.functions.keys().cloned())
+        .chain(shell.aliases.keys().cloned())
+        .collect();
+    let path = shell.var("PATH").unwrap_or_default();
+    for dir in env::split_paths(&path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            if let Ok(name) = entry.file_name().into_string()
+                && name.starts_with(prefix)
+                && entry
+                    .metadata()
+                    .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+            {
+                names.insert(name);
+            }
+        }
+    }
+    candidates(names.iter().map(String::as_str), prefix)
+}
+
+fn flags(command: &str, prefix: &str) -> Vec<Candidate> {
+    let flags: &[&str] = match command {
+        "set" => &["-o", "+o", "-C", "-e", "-u", "-x"],
+        "unalias" => &["-a"],
+        "trust" => &["-l"],
+        "jobs" => &["-l", "-p"],
+        "disown" => &["-a"],
+        "kill" => &["-l", "-s"],
+        "trap" => &["-l", "-p"],
+        "terminal" => &["-c", "--norc"],
+        command => builtin_flags(command).unwrap_or_default(),
+    };
+    candidates(flags.iter().copied(), prefix)
+}
+
+/// The names `set -o` knows.
+fn options(shell: &Shell, prefix: &str) -> Vec<Candidate> {
+    let mut options = shell.options.clone();
+    let names: Vec<&str> = options.named().iter().map(|(name, _)| *name).collect();
+    candidates(names, prefix)
+}
+
+/// Files in the directory the word names so far, only directories after
+/// `cd`. Hidden files only show up once a `.` is typed.
+fn paths(shell: &Shell, word: &str, only_dirs: bool) -> Vec<Candidate> {
+    let raw = unquote(word);
+    let (dir, prefix) = match raw.rfind('/') {
+        Some(slash) => raw.split_at(slash + 1),
+        None => ("", raw.as_str()),
+    };
+    let searched = shell.now_dir.join(if dir.is_empty() { "." } else { dir });
+    let Ok(entries) = fs::read_dir(&searched) else {
+        return Vec::new();
+    };
+    let mut found: Vec<(String, bool)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let is_dir = searched.join(&name).is_dir();
+            (name.starts_with(prefix)
+                && (prefix.starts_with('.') || !name.starts_with('.'))
+                && (is_dir || !only_dirs))
+                .then_some((name, is_dir))
+        })
+        .collect();
+    found.sort();
+    // a quote the word started with stays, the rest is escaped
+    let quote = word.chars().next().filter(|ch| matches!(ch, '\'' | '"'));
+    found
+        .into_iter()
+        .map(|(name, is_dir)| {
+            let slash = if is_dir { "/" } else { "" };
+            let path = format!("{}{}{}", dir, name, slash);
+            Candidate {
+                text: match quote {
+                    Some(quote) => format!("{}{}", quote, path),
+                    None => escape(&path),
+                },
+                display: format!("{}{}", name, slash),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,163 @@
+//! Colors for the line being typed, from the same tokens the parser gets:
+//! commands green when they can run and red when not, keywords blue,
+//! quoted text yellow, redirections magenta and a quote that isn't closed
+//! red up to the end.
+
+use super::{Shell, builtins};
+use crate::command_build::{
+    expand::{Variables, assignment},
+    parse::{CLOSERS, OPENERS, Spanned, Token, tokenize},
+};
+use crate::command_work::{BUILTINS, find_program};
+use crate::line_editor::Span;
+
+const COMMAND: &str = "\x1b[32m";
+const UNKNOWN: &str = "\x1b[31m";
+const KEYWORD: &str = "\x1b[34m";
+const QUOTED: &str = "\x1b[33m";
+const REDIRECT: &str = "\x1b[35m";
+const UNCLOSED: &str = "\x1b[1;31m";
+
+pub fn highlight(shell: &Shell, line: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut end = line.len();
+    if let Some(quote) = unclosed_quote(line) {
+        end = quote;
+        spans.push(Span {
+            start: quote,
+            end: line.len(),
+            style: UNCLOSED,
+        });
+    } else if line.ends_with('\\') {
+        end -= 1;
+    }
+    // an unfinished here-document leaves the line uncolored
+    let Ok(tokens) = tokenize(&line[..end]) else {
+        return spans;
+    };
+    let mut words = Vec::new();
+    let mut command_next = true;
+    let mut target_next = false;
+    for Spanned { token, start, end } in &tokens {
+        let style = match token {
+            Token::Word(_) if target_next => {
+                target_next = false;
+                Some(REDIRECT)
+            }
+            Token::Word(word) if command_next => {
+                let text = &line[*start..*end];
+                if OPENERS.contains(&text) || CLOSERS.contains(&text) || text == "function" {
+                    // a name comes after `for`, `case` and `function`, an
+                    // operator after the keywords that close something
+                    command_next = !matches!(
+                        text,
+                        "for" | "case" | "function" | "fi" | "done" | "esac" | "}"
+                    );
+                    Some(KEYWORD)
+                } else if assignment(word).is_some() {
+                    None
+                } else {
+                    command_next = false;
+                    Some(if is_command(shell, word) {
+                        COMMAND
+                    } else {
+                        UNKNOWN
+                    })
+                }
+            }
+            Token::Word(_) => None,
+            Token::Redirect { .. } => {
+                target_next = true;
+                Some(REDIRECT)
+            }
+            _ => {
+                command_next = true;
+                None
+            }
+        };
+        if let Some(style) = style {
+            spans.push(Span {
+                start: *start,
+                end: *end,
+                style,
+            });
+        }
+        if matches!(token, Token::Word(_)) {
+            words.push((*start, *end));
+        }
+    }
+    for (start, end) in words {
+        quoted(line, start, end, &mut spans);
+    }
+    spans
+}
+
+/// Whether running `word` would find something.
+fn is_command(shell: &Shell, word: &str) -> bool {
+    // quoted names still need their quotes removed to be looked up
+    let name: String = word
+        .chars()
+        .filter(|ch| !matches!(ch, '\'' | '"' | '\\'))
+        .collect();
+    BUILTINS.contains(&name.as_str())
+        || builtins::lookup(&name).is_some()
+        || shell.functions.contains_key(&name)
+        || shell.aliases.contains_key(&name)
+        || name.contains('$')
+        || find_program(
+            &name,
+            &shell.now_dir,
+            &shell.var("PATH").unwrap_or_default(),
+        )
+        .is_some()
+}
+
+/// The quoted parts of the word at `start..end`.
+fn quoted(line: &str, start: usize, end: usize, spans: &mut Vec<Span>) {
+    let mut quote: Option<(char, usize)> = None;
+    let mut chars = line[start..end].char_indices();
+    while let Some((i, ch)) = chars.next() {
+        match (quote, ch) {
+            (None | Some(('"', _)), '\\') => {
+                chars.next();
+            }
+            (None, '\'' | '"') => quote = Some((ch, i)),
+            (Some((q, from)), ch) if q == ch => {
+                spans.push(Span {
+                    start: start + from,
+                    end: start + i + 1,
+                    style: QUOTED,
+                });
+                quote = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Where the quote that is still open at the end of the line starts.
+fn unclosed_quote(line: &str) -> Option<usize> {
+    let mut quote: Option<(char, usize)> = None;
+    let mut word_start = true;
+    let mut chars = line.char_indices();
+    while let Some((i, ch)) = chars.next() {
+        match (quote, ch) {
+            (None | Some(('"', _)), '\\') => {
+                chars.next();
+            }
+            (None, '#') if word_start => {
+                chars
+                    .by_ref()
+                    .take_while(|&(_, c)| c != '\n')
+                    .for_each(drop);
+                continue;
+            }
+            (None, '\'' | '"') => quote = Some((ch, i)),
+            (Some((q, _)), ch) if q == ch => quote = None,
+            _ => {}
+        }
+        word_start =
+            quote.is_none() && matches!(ch, ' ' | '\t' | '\n' | ';' | '|' | '&' | '(' | ')');
+    }
+    quote.map(|(_, start)| start)
+}
@@ -0,0 +1,581 @@
+//! The history file: one command per line with when, where and how it
+//! ran, shared by every session of the user.
+//!
+//! A line is `time<TAB>duration<TAB>status<TAB>cwd<TAB>command`, the time
+//! in seconds since the epoch, the duration in milliseconds, and tabs,
+//! newlines and backslashes of the command and the directory escaped. Lines
+//! without tabs come from older versions that only kept the command.
+
+use std::{
+    collections::HashSet,
+    env,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::command_build::{
+    command::{FAILURE, SUCCESS, Status},
+    parse::{Token, tokenize},
+};
+
+#[derive(Clone)]
+pub struct Entry {
+    /// Seconds since the epoch when the command started.
+    pub time: u64,
+    pub duration: Duration,
+    pub status: Status,
+    pub cwd: PathBuf,
+    pub command: String,
+}
+
+/// `$XDG_STATE_HOME/shu/history` if that is set, `~/.shu_history`
+/// otherwise, and a `.shu_history` in `cwd` without a home.
+pub fn default_location(cwd: &Path) -> PathBuf {
+    if let Some(state) = env::var_os("XDG_STATE_HOME").filter(|state| !state.is_empty()) {
+        return PathBuf::from(state).join("shu").join("history");
+    }
+    env::home_dir()
+        .unwrap_or_else(|| cwd.to_path_buf())
+        .join(".shu_history")
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Holds an `flock` on the file while it lives, so sessions writing at
+/// the same time don't mix their lines.
+struct Locked(File);
+
+impl Locked {
+    fn new(file: File, operation: i32) -> io::Result<Self> {
+        // SAFETY: the descriptor is open for as long as `file` lives
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(file))
+    }
+}
+
+impl Drop for Locked {
+    fn drop(&mut self) {
+        // SAFETY: same descriptor as in `new`
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+pub fn append(path: &Path, entry: &Entry) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut file = Locked::new(file, libc::LOCK_EX)?;
+    // a single write, so a reader never sees half a line
+    file.0.write_all(entry.to_line().as_bytes())
+}
+
+/// Every entry, oldest first. A missing file is an empty history.
+pub fn load(path: &Path) -> io::Result<Vec<Entry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut file = Locked::new(file, libc::LOCK_SH)?;
+    let mut text = String::new();
+    file.0.read_to_string(&mut text)?;
+    Ok(text.lines().filter_map(Entry::parse).collect())
+}
+
+/// Empties the history of every session.
+pub fn clear(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)?;
+    let file = Locked::new(file, libc::LOCK_EX)?;
+    file.0.set_len(0)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        result.push(match chars.next() {
+            Some('t') => '\t',
+            Some('n') => '\n',
+            Some(other) => other,
+            None => '\\',
+        });
+    }
+    result
+}
+
+impl Entry {
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            self.time,
+            self.duration.as_millis(),
+            self.status,
+            escape(&self.cwd.to_string_lossy()),
+            escape(&self.command)
+        )
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        if line.trim().is_empty() {
+            return None;
+        }
+        let fields: Vec<&str> = line.splitn(5, '\t').collect();
+        let [time, duration, status, cwd, command] = fields[..] else {
+            return Some(Self::legacy(line));
+        };
+        Some(Self {
+            time: time.parse().ok()?,
+            duration: Duration::from_millis(duration.parse().ok()?),
+            status: status.parse().ok()?,
+            cwd: PathBuf::from(unescape(cwd)),
+            command: unescape(command),
+        })
+    }
+
+    /// `command ` or `command ERROR` as the first versions wrote, or
+    /// `command ERROR status` once statuses were numbers.
+    fn legacy(line: &str) -> Self {
+        let (command, status) = if let Some(command) = line.strip_suffix(" ERROR") {
+            (command, FAILURE)
+        } else if let Some((command, status)) = line.rsplit_once(" ERROR ")
+            && let Ok(status) = status.parse()
+        {
+            (command, status)
+        } else {
+            (line.strip_suffix(' ').unwrap_or(line), SUCCESS)
+        };
+        Self {
+            time: 0,
+            duration: Duration::ZERO,
+            status,
+            cwd: PathBuf::new(),
+            command: command.to_string(),
+        }
+    }
+}
+
+/// What `history` shows, every field is a condition an entry has to meet.
+#[derive(Default)]
+pub struct Filter {
+    pub cwd: Option<PathBuf>,
+    pub status: Option<Status>,
+    pub failed: bool,
+    /// Only entries at least this recent.
+    pub since: Option<u64>,
+    /// Words the command has to contain.
+    pub words: Vec<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.cwd.as_ref().is_none_or(|cwd| *cwd == entry.cwd)
+            && self.status.is_none_or(|status| status == entry.status)
+            && (!self.failed || entry.status != SUCCESS)
+            && self.since.is_none_or(|since| entry.time >= since)
+            && self
+                .words
+                .iter()
+                .all(|word| entry.command.contains(word.as_str()))
+    }
+}
+
+/// `30`, `30s`, `10m`, `2h` or `7d` as seconds.
+pub fn parse_age(age: &str) -> Option<u64> {
+    let (number, unit) = match age.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => age.split_at(i),
+        None => (age, "s"),
+    };
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in local time.
+pub fn format_time(time: u64) -> String {
+    let time = time as libc::time_t;
+    // SAFETY: tm is plain data that localtime_r fills in
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&time, &mut tm);
+        tm
+    };
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// `350ms`, `4.2s` or `3m05s`.
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis < 1000 {
+        format!("{}ms", millis)
+    } else if millis < 60_000 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else {
+        format!("{}m{:02}s", millis / 60_000, millis / 1000 % 60)
+    }
+}
+
+/// The entries Ctrl-R offers for `query`: each command once, as it ran
+/// last, the best match first and the most recent first among equals. Only
+/// those run in `cwd` when it is given. With each entry come the bytes of
+/// its command the query matched.
+pub fn search<'e>(
+    entries: &'e [Entry],
+    query: &str,
+    cwd: Option<&Path>,
+) -> Vec<(&'e Entry, Vec<usize>)> {
+    let mut seen = HashSet::new();
+    let mut hits: Vec<(i64, &Entry, Vec<usize>)> = entries
+        .iter()
+        .rev()
+        .filter(|entry| cwd.is_none_or(|cwd| cwd == entry.cwd))
+        .filter(|entry| seen.insert(entry.command.as_str()))
+        .filter_map(|entry| {
+            let (score, matches) = fuzzy_score(query, &entry.command)?;
+            Some((score, entry, matches))
+        })
+        .collect();
+    // stable, so the most recent stays first among equals
+    hits.sort_by_key(|&(score, _, _)| std::cmp::Reverse(score));
+    hits.into_iter()
+        .map(|(_, entry, matches)| (entry, matches))
+        .collect()
+}
+
+/// How well `query` matches `text` when its characters come in that order
+/// with maybe others in between, `None` when they don't. Characters that
+/// follow each other, start a word or come early count more. Case only
+/// matters when the query has an uppercase letter.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let exact = query.chars().any(char::is_uppercase);
+    let same = |a: char, b: char| {
+        if exact {
+            a == b
+        } else {
+            a.to_lowercase().eq(b.to_lowercase())
+        }
+    };
+    let query: Vec<char> = query.chars().collect();
+    let text: Vec<(usize, char)> = text.char_indices().collect();
+    let Some((&first, rest)) = query.split_first() else {
+        return Some((0, Vec::new()));
+    };
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    // the first match of the rest after each place the query can start
+    for start in 0..text.len() {
+        if !same(text[start].1, first) {
+            continue;
+        }
+        let mut positions = vec![start];
+        for &ch in rest {
+            let at = positions[positions.len() - 1] + 1;
+            match text[at..].iter().position(|&(_, c)| same(c, ch)) {
+                Some(i) => positions.push(at + i),
+                // starting later can't find it either
+                None => return best.map(|(score, positions)| (score, bytes(&text, positions))),
+            }
+        }
+        let score = positions.iter().enumerate().fold(0, |score, (n, &i)| {
+            let follows = n > 0 && positions[n - 1] + 1 == i;
+            let word_start = i == 0 || !text[i - 1].1.is_alphanumeric();
+            let gap = if n > 0 { i - positions[n - 1] - 1 } else { 0 };
+            score + 16 + if follows { 24 } else { 0 } + if word_start { 12 } else { 0 }
+                - gap.min(8) as i64
+        }) - start.min(16) as i64;
+        if best.as_ref().is_none_or(|(best, _)| score > *best) {
+            best = Some((score, positions));
+        }
+    }
+    best.map(|(score, positions)| (score, bytes(&text, positions)))
+}
+
+/// Character positions as byte offsets.
+fn bytes(text: &[(usize, char)], positions: Vec<usize>) -> Vec<usize> {
+    positions.into_iter().map(|i| text[i].0).collect()
+}
+
+/// Bash style history expansion of a line typed at the prompt:
+///
+/// - `!!` the last command, `!n` command number `n` (as `history` numbers
+///   them), `!-n` the `n`-th last one and `!prefix` the last one starting
+///   with `prefix`
+/// - `!$`, `!^` and `!*` the last word, the first argument and all the
+///   arguments of the last command
+/// - `^old^new` at the start of the line: the last command with the first
+///   `old` replaced by `new`
+///
+/// Nothing is expanded inside single quotes or after a backslash, nor a `!`
+/// followed by a blank, `=`, `(` or the end of the line. `None` when the
+/// line has nothing to expand.
+pub fn expand(line: &str, entries: &[Entry]) -> Result<Option<String>, String> {
+    let last = || {
+        entries
+            .last()
+            .map(|entry| entry.command.as_str())
+            .ok_or_else(|| "!!: event not found".to_string())
+    };
+    if let Some(rest) = line.strip_prefix('^') {
+        let mut parts = rest.splitn(3, '^');
+        let old = parts.next().unwrap_or_default();
+        let new = parts.next().unwrap_or_default();
+        let previous = last()?;
+        if old.is_empty() || !previous.contains(old) {
+            return Err(format!("{}: substitution failed", line));
+        }
+        return Ok(Some(previous.replacen(old, new, 1)));
+    }
+
+    let mut result = String::new();
+    let mut expanded = false;
+    let mut quote = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((_, ch)) = chars.next() {
+        match (quote, ch) {
+            (None | Some('"'), '\\') => {
+                result.push(ch);
+                result.extend(chars.next().map(|(_, ch)| ch));
+                continue;
+            }
+            (None, '\'' | '"') => quote = Some(ch),
+            (Some(q), ch) if q == ch => quote = None,
+            (None | Some('"'), '!') => {
+                let Some(&(_, next)) = chars.peek() else {
+                    result.push(ch);
+                    continue;
+                };
+                let event = match next {
+                    '!' | '$' | '^' | '*' => {
+                        chars.next();
+                        let command = last()?;
+                        let words = words(command);
+                        match next {
+                            '!' => command.to_string(),
+                            '$' => words.last().copied().unwrap_or_default().to_string(),
+                            '^' => words.get(1).copied().unwrap_or_default().to_string(),
+                            _ => words.get(1..).unwrap_or_default().join(" "),
+                        }
+                    }
+                    '-' | '0'..='9' => {
+                        let mut number = String::from(next);
+                        chars.next();
+                        while let Some((_, digit)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                            number.push(digit);
+                        }
+                        let index = match number.strip_prefix('-') {
+                            Some(back) => back
+                                .parse::<usize>()
+                                .ok()
+                                .and_then(|back| entries.len().checked_sub(back)),
+                            None => number.parse::<usize>().ok().and_then(|n| n.checked_sub(1)),
+                        };
+                        match index.and_then(|index| entries.get(index)) {
+                            Some(entry) => entry.command.clone(),
+                            None => return Err(format!("!{}: event not found", number)),
+                        }
+                    }
+                    next if is_event_char(next) => {
+                        let mut prefix = String::new();
+                        while let Some((_, c)) = chars.next_if(|&(_, c)| is_event_char(c)) {
+                            prefix.push(c);
+                        }
+                        match entries
+                            .iter()
+                            .rev()
+                            .find(|entry| entry.command.starts_with(&prefix))
+                        {
+                            Some(entry) => entry.command.clone(),
+                            None => return Err(format!("!{}: event not found", prefix)),
+                        }
+                    }
+                    _ => {
+                        result.push(ch);
+                        continue;
+                    }
+                };
+                result.push_str(&event);
+                expanded = true;
+                continue;
+            }
+            _ => {}
+        }
+        result.push(ch);
+    }
+    Ok(expanded.then_some(result))
+}
+
+/// What can follow `!` in `!prefix`.
+fn is_event_char(ch: char) -> bool {
+    !ch.is_whitespace()
+        && !matches!(
+            ch,
+            '=' | '(' | ')' | ';' | '|' | '&' | '<' | '>' | '"' | '\''
+        )
+}
+
+/// The words of a command as typed, quotes included.
+fn words(command: &str) -> Vec<&str> {
+    match tokenize(command) {
+        Ok(tokens) => tokens
+            .iter()
+            .filter(|spanned| matches!(spanned.token, Token::Word(_)))
+            .map(|spanned| &command[spanned.start..spanned.end])
+            .collect(),
+        Err(_) => command.split_whitespace().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(line: &str) -> (String, Status) {
+        let entry = Entry::parse(line).unwrap();
+        (entry.command, entry.status)
+    }
+
+    #[test]
+    fn reads_the_lines_of_older_versions() {
+        assert_eq!(parsed("ls -la "), ("ls -la".to_string(), SUCCESS));
+        assert_eq!(parsed("cat nope ERROR"), ("cat nope".to_string(), FAILURE));
+        assert_eq!(parsed("grep x ERROR 2"), ("grep x".to_string(), 2));
+        assert_eq!(
+            parsed("echo ERROR x "),
+            ("echo ERROR x".to_string(), SUCCESS)
+        );
+    }
+
+    fn entries(commands: &[&str]) -> Vec<Entry> {
+        commands
+            .iter()
+            .map(|command| Entry {
+                time: 0,
+                duration: Duration::ZERO,
+                status: SUCCESS,
+                cwd: PathBuf::from(if command.starts_with("git") {
+                    "/repo"
+                } else {
+                    "/"
+                }),
+                command: command.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expands_events_and_words() {
+        let entries = entries(&["make", "git checkout main", "cp 'a b' c/"]);
+        let expand = |line| expand(line, &entries);
+        assert_eq!(expand("!!"), Ok(Some("cp 'a b' c/".to_string())));
+        assert_eq!(expand("sudo !!"), Ok(Some("sudo cp 'a b' c/".to_string())));
+        assert_eq!(
+            expand("!1 && !-2"),
+            Ok(Some("make && git checkout main".to_string()))
+        );
+        assert_eq!(expand("!git"), Ok(Some("git checkout main".to_string())));
+        assert_eq!(expand("ls !$ !^"), Ok(Some("ls c/ 'a b'".to_string())));
+        assert_eq!(expand("echo !*"), Ok(Some("echo 'a b' c/".to_string())));
+        assert_eq!(
+            expand("^main^dev"),
+            Err("^main^dev: substitution failed".to_string())
+        );
+        assert_eq!(expand("^c/^d/"), Ok(Some("cp 'a b' d/".to_string())));
+    }
+
+    #[test]
+    fn leaves_what_is_not_an_event() {
+        let entries = entries(&["make"]);
+        let expand = |line| expand(line, &entries);
+        assert_eq!(expand("echo hi"), Ok(None));
+        assert_eq!(expand("echo '!!' \\!! a! ! !=x"), Ok(None));
+        assert_eq!(expand("echo \"!!\""), Ok(Some("echo \"make\"".to_string())));
+        assert_eq!(expand("!9"), Err("!9: event not found".to_string()));
+        assert_eq!(expand("!nope"), Err("!nope: event not found".to_string()));
+        assert_eq!(
+            super::expand("!!", &[]),
+            Err("!!: event not found".to_string())
+        );
+    }
+
+    #[test]
+    fn fuzzy_scores_prefer_word_starts_and_runs() {
+        let (_, matched) = fuzzy_score("gco", "git checkout").unwrap();
+        assert_eq!(matched, [0, 4, 9]);
+        assert!(fuzzy_score("xyz", "git checkout").is_none());
+        assert_eq!(fuzzy_score("", "ls"), Some((0, Vec::new())));
+        let score = |query, text| fuzzy_score(query, text).unwrap().0;
+        assert!(score("che", "git checkout") > score("che", "cat header"));
+        assert!(score("ls", "ls -la") > score("ls", "cat files"));
+        // lowercase matches any case, uppercase only itself
+        assert!(fuzzy_score("readme", "cat README").is_some());
+        assert!(fuzzy_score("README", "cat readme").is_none());
+        // offsets are in bytes
+        assert_eq!(fuzzy_score("b", "é b").unwrap().1, [3]);
+    }
+
+    #[test]
+    fn search_keeps_each_command_once_best_first() {
+        let entries = entries(&["git commit", "make", "git checkout", "git commit"]);
+        let found = |query, cwd: Option<&Path>| -> Vec<String> {
+            search(&entries, query, cwd)
+                .into_iter()
+                .map(|(entry, _)| entry.command.clone())
+                .collect()
+        };
+        assert_eq!(found("gc", None), ["git commit", "git checkout"]);
+        assert_eq!(found("check", None), ["git checkout"]);
+        assert_eq!(found("", Some(Path::new("/"))), ["make"]);
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        let entry = Entry {
+            time: 1_700_000_000,
+            duration: Duration::from_millis(42),
+            status: 3,
+            cwd: PathBuf::from("/tmp/a\tb"),
+            command: "echo 'one\ntwo' \\".to_string(),
+        };
+        let back = Entry::parse(entry.to_line().trim_end_matches('\n')).unwrap();
+        assert_eq!(back.time, entry.time);
+        assert_eq!(back.duration, entry.duration);
+        assert_eq!(back.status, entry.status);
+        assert_eq!(back.cwd, entry.cwd);
+        assert_eq!(back.command, entry.command);
+    }
+}
@@ -0,0 +1,468 @@
+//! Work the shell keeps track of while it goes on: lists started with `&`
+//! and programs stopped with Ctrl-Z. Jobs are numbered from 1 (`%1`), the
+//! last one is the current job (`%+`) and the one before the previous
+//! (`%-`).
+//!
+//! With a terminal to control, every pipeline of external programs gets a
+//! process group of its own, and only the one in the foreground gets the
+//! terminal: that is what Ctrl-Z and Ctrl-C reach, and what `fg` and `bg`
+//! move around.
+
+use std::{
+    fmt,
+    io::{self, IsTerminal},
+    os::fd::RawFd,
+    sync::{
+        Arc, Condvar, Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicI32, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::signals::terminates;
+use crate::command_build::command::{Cancel, INTERRUPTED, Status, cancelled};
+
+/// How often a wait for a job looks for a Ctrl-C.
+const POLL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum State {
+    Running,
+    Stopped,
+    Done(Status),
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Running => write!(f, "Running"),
+            Self::Stopped => write!(f, "Stopped"),
+            Self::Done(0) => write!(f, "Done"),
+            Self::Done(status) => write!(f, "Exit {}", status),
+        }
+    }
+}
+
+/// What the programs of a job (or of the shell itself) and the shell
+/// share: the state of the job, the process group its programs are in right
+/// now and their pids.
+pub struct Control {
+    state: Mutex<State>,
+    changed: Condvar,
+    /// Set by the first program of a pipeline, 0 between pipelines.
+    pgid: AtomicI32,
+    pids: Mutex<Vec<libc::pid_t>>,
+    /// Its programs get the terminal: the commands typed at the prompt, or
+    /// a job brought back with `fg`.
+    foreground: AtomicBool,
+    /// Stops of a job only change its state, a stop in the commands of the
+    /// shell itself makes a new job of what stopped.
+    job: bool,
+    /// What stops its builtins: Ctrl-C for the shell, `kill` for a job.
+    cancel: Cancel,
+    /// The signal `kill` sent that cancelled it, 0 for none.
+    killed: AtomicI32,
+}
+
+impl Control {
+    /// The commands of the shell itself.
+    pub fn shell() -> Self {
+        Self::new(State::Running, true, false, Cancel::default())
+    }
+
+    pub fn job(state: State) -> Self {
+        Self::new(state, false, true, Cancel::job())
+    }
+
+    fn new(state: State, foreground: bool, job: bool, cancel: Cancel) -> Self {
+        Self {
+            state: Mutex::new(state),
+            changed: Condvar::new(),
+            pgid: AtomicI32::new(0),
+            pids: Mutex::new(Vec::new()),
+            foreground: AtomicBool::new(foreground),
+            job,
+            cancel,
+            killed: AtomicI32::new(0),
+        }
+    }
+
+    pub fn is_job(&self) -> bool {
+        self.job
+    }
+
+    pub fn cancel(&self) -> &Cancel {
+        &self.cancel
+    }
+
+    /// The signal `kill` cancelled it with, if that is what did.
+    pub fn killed(&self) -> Option<libc::c_int> {
+        Some(self.killed.load(Ordering::SeqCst)).filter(|&signal| signal != 0)
+    }
+
+    /// What a builtin cancelled in it reports: `128 + signal` after `kill`,
+    /// like a program the signal ended, `INTERRUPTED` after a Ctrl-C.
+    pub fn cancelled_status(&self) -> Status {
+        self.killed().map_or(INTERRUPTED, |signal| 128 + signal)
+    }
+
+    pub fn state(&self) -> State {
+        *lock(&self.state)
+    }
+
+    pub fn set_state(&self, state: State) {
+        *lock(&self.state) = state;
+        self.changed.notify_all();
+    }
+
+    /// Lets a stopped job go on; one that ended stays so.
+    pub fn resume(&self) -> io::Result<()> {
+        {
+            let mut state = lock(&self.state);
+            if *state == State::Stopped {
+                *state = State::Running;
+            }
+        }
+        self.changed.notify_all();
+        self.signal(libc::SIGCONT)
+    }
+
+    /// Blocks while the job is running, and tells what it became. A Ctrl-C
+    /// meanwhile is passed on to the job with `forward`, otherwise it ends
+    /// the wait.
+    pub fn wait(&self, forward: bool) -> State {
+        let mut state = lock(&self.state);
+        while *state == State::Running {
+            if cancelled() {
+                if !forward {
+                    break;
+                }
+                self.cancel.set();
+            }
+            state = self
+                .changed
+                .wait_timeout(state, POLL)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+        *state
+    }
+
+    /// The process group the next program joins, `0` for a new one.
+    pub fn pgid(&self) -> libc::pid_t {
+        self.pgid.load(Ordering::SeqCst)
+    }
+
+    /// The process `jobs -p` shows: the leader of its process group, or
+    /// its first program without job control.
+    pub fn leader(&self) -> Option<libc::pid_t> {
+        match self.pgid() {
+            0 => lock(&self.pids).first().copied(),
+            pgid => Some(pgid),
+        }
+    }
+
+    pub fn is_foreground(&self) -> bool {
+        self.foreground.load(Ordering::SeqCst)
+    }
+
+    pub fn set_foreground(&self, foreground: bool) {
+        self.foreground.store(foreground, Ordering::SeqCst);
+    }
+
+    /// A program of the job started, the first one of a pipeline leads
+    /// its process group.
+    pub fn started(&self, pid: libc::pid_t, terminal: Option<RawFd>) {
+        lock(&self.pids).push(pid);
+        if terminal.is_none() {
+            return;
+        }
+        let _ = self
+            .pgid
+            .compare_exchange(0, pid, Ordering::SeqCst, Ordering::SeqCst);
+        if let Some(tty) = terminal
+            && self.is_foreground()
+        {
+            give_terminal(tty, self.pgid());
+        }
+    }
+
+    pub fn ended(&self, pid: libc::pid_t) {
+        lock(&self.pids).retain(|&running| running != pid);
+    }
+
+    /// Hands programs that stopped over to `job`.
+    pub fn hand_over(&self, pids: &[libc::pid_t], job: &Control) {
+        lock(&self.pids).retain(|pid| !pids.contains(pid));
+        lock(&job.pids).extend(pids);
+        job.pgid.store(self.pgid(), Ordering::SeqCst);
+    }
+
+    /// The pipeline that started the process group is over (or stopped):
+    /// the next one gets a new group, and the shell the terminal back.
+    pub fn release(&self, terminal: Option<RawFd>) {
+        self.pgid.store(0, Ordering::SeqCst);
+        if let Some(tty) = terminal
+            && self.is_foreground()
+        {
+            // SAFETY: getpgrp can't fail
+            give_terminal(tty, unsafe { libc::getpgrp() });
+        }
+    }
+
+    /// Sends `signal` to the programs of the job: to its process group,
+    /// or to each of them without job control. Its builtins are cancelled
+    /// by the signals that would end a program.
+    pub fn signal(&self, signal: libc::c_int) -> io::Result<()> {
+        if terminates(signal) {
+            self.killed.store(signal, Ordering::SeqCst);
+            self.cancel.set();
+        }
+        let pgid = self.pgid();
+        let targets = if pgid > 0 {
+            vec![-pgid]
+        } else {
+            lock(&self.pids).clone()
+        };
+        for target in targets {
+            // SAFETY: kill only sends a signal
+            if unsafe { libc::kill(target, signal) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Makes `pgid` the foreground process group of the terminal.
+pub fn give_terminal(tty: RawFd, pgid: libc::pid_t) {
+    if pgid > 0 {
+        // SAFETY: tcsetpgrp only changes the terminal, SIGTTOU is ignored
+        unsafe { libc::tcsetpgrp(tty, pgid) };
+    }
+}
+
+/// Starts job control on the terminal the shell reads from: the shell gets
+/// a process group of its own and the terminal. The descriptor returned is
+/// the terminal, for handing it to the programs.
+pub fn take_terminal() -> Option<RawFd> {
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+    // SAFETY: plain system calls on the shell's own process and stdin; the
+    // shell has to be able to take the terminal back from the background
+    unsafe {
+        libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::setpgid(0, 0);
+        libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+        let tty = libc::fcntl(libc::STDIN_FILENO, libc::F_DUPFD_CLOEXEC, 10);
+        (tty >= 0).then_some(tty)
+    }
+}
+
+struct Job {
+    id: usize,
+    text: String,
+    control: Arc<Control>,
+    handle: Option<JoinHandle<()>>,
+    /// The state last reported, so each change is told once.
+    shown: State,
+}
+
+/// A job as `jobs` shows it.
+#[derive(Clone)]
+pub struct JobRef {
+    pub id: usize,
+    pub text: String,
+    /// `+` for the current job, `-` for the previous one.
+    pub mark: char,
+    pub control: Arc<Control>,
+}
+
+impl JobRef {
+    /// `[1]+  Running                 sleep 10 &`, with the process group
+    /// after the mark when `long`.
+    pub fn line(&self, state: State, long: bool) -> String {
+        let group = match self.control.leader() {
+            Some(pid) if long => format!(" {}", pid),
+            _ => String::new(),
+        };
+        let amp = if state == State::Running { " &" } else { "" };
+        let state = state.to_string();
+        format!(
+            "[{}]{}{}  {:<24}{}{}",
+            self.id, self.mark, group, state, self.text, amp
+        )
+    }
+}
+
+/// Every job of the shell, shared with the copies of the shell running
+/// pipelines and background lists.
+#[derive(Clone, Default)]
+pub struct Jobs(Arc<Mutex<Vec<Job>>>);
+
+impl Jobs {
+    /// Runs `work` in a thread as a new job and gives its number.
+    pub fn spawn(
+        &self,
+        text: String,
+        control: Arc<Control>,
+        work: impl FnOnce() -> Status + Send + 'static,
+    ) -> usize {
+        let done = Arc::clone(&control);
+        let handle = thread::spawn(move || {
+            let status = work();
+            done.set_state(State::Done(status));
+        });
+        let mut jobs = lock(&self.0);
+        let id = jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        let shown = control.state();
+        jobs.push(Job {
+            id,
+            text,
+            control,
+            handle: Some(handle),
+            shown,
+        });
+        id
+    }
+
+    pub fn all(&self) -> Vec<JobRef> {
+        let jobs = lock(&self.0);
+        (0..jobs.len())
+            .map(|index| reference(&jobs, index))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        lock(&self.0).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// How many jobs are running, not stopped or done.
+    pub fn running(&self) -> usize {
+        lock(&self.0)
+            .iter()
+            .filter(|job| job.control.state() == State::Running)
+            .count()
+    }
+
+    /// The job `spec` names: `%n` (or `n`), `%+`/`%%`/`%` or nothing for
+    /// the current one, `%-`, `%prefix` or `%?text` for one whose command
+    /// starts with (or contains) the text.
+    pub fn find(&self, spec: Option<&str>) -> Result<JobRef, String> {
+        let jobs = lock(&self.0);
+        let index = match spec.map(|spec| spec.strip_prefix('%').unwrap_or(spec)) {
+            None | Some("" | "%" | "+") => jobs.len().checked_sub(1),
+            Some("-") => jobs.len().checked_sub(2).or(jobs.len().checked_sub(1)),
+            Some(number) if number.bytes().all(|b| b.is_ascii_digit()) => {
+                let id = number.parse::<usize>().ok();
+                jobs.iter().position(|job| Some(job.id) == id)
+            }
+            Some(text) => match text.strip_prefix('?') {
+                Some(text) => jobs.iter().rposition(|job| job.text.contains(text)),
+                None => jobs.iter().rposition(|job| job.text.starts_with(text)),
+            },
+        };
+        match index {
+            Some(index) => Ok(reference(&jobs, index)),
+            None => Err(match spec {
+                None => "no current job".to_string(),
+                Some(spec) => format!("{}: no such job", spec),
+            }),
+        }
+    }
+
+    /// Every job (or only those that stopped or ended since they were last
+    /// reported) with its state. Reported jobs that ended are forgotten.
+    pub fn report(&self, all: bool) -> Vec<(JobRef, State)> {
+        let mut jobs = lock(&self.0);
+        let mut reported = Vec::new();
+        for index in 0..jobs.len() {
+            let state = jobs[index].control.state();
+            if all || (state != jobs[index].shown && state != State::Running) {
+                reported.push((reference(&jobs, index), state));
+            }
+            jobs[index].shown = state;
+        }
+        let done: Vec<usize> = reported
+            .iter()
+            .filter(|(_, state)| matches!(state, State::Done(_)))
+            .map(|(job, _)| job.id)
+            .collect();
+        jobs.retain(|job| !done.contains(&job.id));
+        reported
+    }
+
+    /// Marks the state of a job as told, the shell said it itself.
+    pub fn shown(&self, id: usize) {
+        if let Some(job) = lock(&self.0).iter_mut().find(|job| job.id == id) {
+            job.shown = job.control.state();
+        }
+    }
+
+    /// Forgets a job: `disown`, or one that ended in the foreground.
+    pub fn remove(&self, id: usize) {
+        lock(&self.0).retain(|job| job.id != id);
+    }
+
+    /// Waits for the job to end, then forgets it. Its status, or `None`
+    /// if it stopped instead (or Ctrl-C ended the wait, without `forward`).
+    pub fn wait(&self, id: usize, forward: bool) -> Option<Status> {
+        let Ok(job) = self.find(Some(&id.to_string())) else {
+            return None;
+        };
+        match job.control.wait(forward) {
+            State::Done(status) => {
+                let handle = lock(&self.0)
+                    .iter_mut()
+                    .find(|job| job.id == id)
+                    .and_then(|job| job.handle.take());
+                if let Some(handle) = handle {
+                    let _ = handle.join();
+                }
+                self.remove(id);
+                Some(status)
+            }
+            _ => None,
+        }
+    }
+
+    /// When the shell ends: stopped jobs are hung up (and woken up to see
+    /// it). Running ones aren't waited for, their programs go on without
+    /// the shell.
+    pub fn finish(&self) {
+        let mut jobs = lock(&self.0);
+        for job in jobs.iter() {
+            if job.control.state() == State::Stopped {
+                let _ = job.control.signal(libc::SIGHUP);
+                let _ = job.control.signal(libc::SIGCONT);
+            }
+        }
+        jobs.clear();
+    }
+}
+
+fn reference(jobs: &[Job], index: usize) -> JobRef {
+    let mark = match jobs.len() - index {
+        1 => '+',
+        2 => '-',
+        _ => ' ',
+    };
+    JobRef {
+        id: jobs[index].id,
+        text: jobs[index].text.clone(),
+        mark,
+        control: Arc::clone(&jobs[index].control),
+    }
+}
@@ -0,0 +1,250 @@
+pub mod builtins;
+pub mod complete;
+pub mod highlight;
+pub mod history;
+pub mod jobs;
+pub mod prompt;
+pub mod rc;
+pub mod signals;
+
+use std::{collections::HashMap, env, os::fd::RawFd, path::PathBuf, process, sync::Arc};
+
+use crate::command_build::{
+    ast::Command,
+    command::{SUCCESS, Status},
+    expand::{Glob, Variables},
+    parse::Aliases,
+};
+use jobs::{Control, Jobs};
+
+#[derive(Clone)]
+pub struct Var {
+    pub value: String,
+    /// Passed in the environment of the programs the shell starts.
+    pub exported: bool,
+}
+
+/// Switches changed with `set -o NAME` / `set +o NAME`, or `set -e` and
+/// the like for those with a letter.
+#[derive(Clone, Default)]
+pub struct Options {
+    /// A glob without matches is an error instead of staying as typed.
+    pub failglob: bool,
+    /// `>` refuses to overwrite an existing file, `>|` still does.
+    pub noclobber: bool,
+    /// `cd` also runs the `.shurc` of the directory it enters (if trusted).
+    pub cdrc: bool,
+    /// A command that fails where nothing tests its status ends the shell.
+    pub errexit: bool,
+    /// Expanding a variable that isn't set is an error.
+    pub nounset: bool,
+    /// Every command is shown on stderr, expanded, before it runs.
+    pub xtrace: bool,
+    /// A pipeline fails with the last stage that failed, not only with the
+    /// last stage.
+    pub pipefail: bool,
+}
+
+impl Options {
+    /// The options `set -X` also changes, by letter.
+    pub const LETTERS: [(char, &'static str); 4] = [
+        ('C', "noclobber"),
+        ('e', "errexit"),
+        ('u', "nounset"),
+        ('x', "xtrace"),
+    ];
+
+    /// Every option by the name `set -o` knows it.
+    pub fn named(&mut self) -> [(&'static str, &mut bool); 7] {
+        [
+            ("cdrc", &mut self.cdrc),
+            ("errexit", &mut self.errexit),
+            ("failglob", &mut self.failglob),
+            ("noclobber", &mut self.noclobber),
+            ("nounset", &mut self.nounset),
+            ("pipefail", &mut self.pipefail),
+            ("xtrace", &mut self.xtrace),
+        ]
+    }
+}
+
+/// Set by `break N`/`continue N` (or `return N`), unwinds the lists running
+/// inside the loop (or function) until the loop itself sees it.
+#[derive(Clone, Copy)]
+pub enum Flow {
+    Break(usize),
+    Continue(usize),
+    Return(Status),
+}
+
+/// Variables a function made `local`, with what they were before the call.
+pub type Frame = HashMap<String, Option<Var>>;
+
+/// Everything a command line can change and the next one can see.
+pub struct Shell {
+    pub now_dir: PathBuf,
+    pub last_status: Status,
+    pub vars: HashMap<String, Var>,
+    pub functions: HashMap<String, Arc<Command>>,
+    pub aliases: Aliases,
+    /// One frame per running function call.
+    pub locals: Vec<Frame>,
+    /// How many functions and sourced files are running, what `return`
+    /// can leave.
+    pub depth: usize,
+    pub options: Options,
+    pub history: PathBuf,
+    /// Positional parameters, `args[0]` is `$0`.
+    pub args: Vec<String>,
+    /// Set by `exit`, stops the rest of the line and the shell itself.
+    pub exit: Option<Status>,
+    pub flow: Option<Flow>,
+    /// How many loops the running command is in.
+    pub loops: usize,
+    /// How many conditions the running command is in (of `if`, `while`, or
+    /// before `&&`/`||`): a failure there is no error.
+    pub testing: usize,
+    /// What `trap` runs, by `EXIT`, `ERR` or signal name. An empty command
+    /// ignores the signal.
+    pub traps: HashMap<String, String>,
+    /// A trap is running, no other one starts meanwhile.
+    pub trapping: bool,
+    /// The failure a command ended with was already told about (ERR trap,
+    /// `set -e`) inside the function or compound command that ran it.
+    pub reported: bool,
+    pub jobs: Jobs,
+    /// The job the commands run for, or the shell itself.
+    pub control: Arc<Control>,
+    /// The terminal, when the shell does job control on it.
+    pub terminal: Option<RawFd>,
+}
+
+impl Shell {
+    pub fn new(now_dir: PathBuf, history: PathBuf) -> Self {
+        let vars = env::vars()
+            .map(|(name, value)| {
+                (
+                    name,
+                    Var {
+                        value,
+                        exported: true,
+                    },
+                )
+            })
+            .collect();
+        Self {
+            now_dir,
+            last_status: SUCCESS,
+            vars,
+            functions: HashMap::new(),
+            aliases: Aliases::new(),
+            locals: Vec::new(),
+            depth: 0,
+            options: Options::default(),
+            history,
+            args: vec!["shu".to_string()],
+            exit: None,
+            flow: None,
+            loops: 0,
+            testing: 0,
+            traps: HashMap::new(),
+            trapping: false,
+            reported: false,
+            jobs: Jobs::default(),
+            control: Arc::new(Control::shell()),
+            terminal: None,
+        }
+    }
+
+    /// `$HOME`, or the home directory of the user without it.
+    pub fn home(&self) -> Option<PathBuf> {
+        self.var("HOME").map(Into::into).or_else(env::home_dir)
+    }
+
+    /// A copy of the state for something that must not change this shell:
+    /// a background list or one stage of a pipeline. Traps aren't copied,
+    /// they belong to the shell that set them.
+    pub fn subshell(&self) -> Self {
+        Self {
+            now_dir: self.now_dir.clone(),
+            last_status: self.last_status,
+            vars: self.vars.clone(),
+            functions: self.functions.clone(),
+            aliases: self.aliases.clone(),
+            locals: self.locals.clone(),
+            depth: self.depth,
+            options: self.options.clone(),
+            history: self.history.clone(),
+            args: self.args.clone(),
+            exit: None,
+            flow: None,
+            loops: 0,
+            testing: self.testing,
+            traps: HashMap::new(),
+            trapping: false,
+            reported: false,
+            jobs: self.jobs.clone(),
+            control: Arc::clone(&self.control),
+            terminal: self.terminal,
+        }
+    }
+
+    /// The rest of the running lists is skipped: `exit`, `break`, Ctrl-C...
+    pub fn stopped(&self) -> bool {
+        self.exit.is_some() || self.flow.is_some() || self.control.cancel().is_set()
+    }
+
+    /// Sets a variable, keeping it exported if it already was.
+    pub fn set_var(&mut self, name: &str, value: String) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.value = value,
+            None => {
+                let var = Var {
+                    value,
+                    exported: false,
+                };
+                self.vars.insert(name.to_string(), var);
+            }
+        }
+    }
+
+    /// The environment for a child process: every exported variable.
+    pub fn environment(&self) -> Vec<(String, String)> {
+        self.vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (name.clone(), var.value.clone()))
+            .collect()
+    }
+
+    /// How the words of a command are globbed right now.
+    pub fn glob(&self) -> Glob<'_> {
+        Glob {
+            dir: &self.now_dir,
+            fail: self.options.failglob,
+        }
+    }
+}
+
+impl Variables for Shell {
+    fn var(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "$" => Some(process::id().to_string()),
+            "#" => Some(self.params().len().to_string()),
+            "@" | "*" => Some(self.params().join(" ")),
+            _ => match name.parse::<usize>() {
+                Ok(n) => self.args.get(n).cloned(),
+                Err(_) => self.vars.get(name).map(|var| var.value.clone()),
+            },
+        }
+    }
+
+    fn params(&self) -> Vec<String> {
+        self.args.iter().skip(1).cloned().collect()
+    }
+
+    fn nounset(&self) -> bool {
+        self.options.nounset
+    }
+}
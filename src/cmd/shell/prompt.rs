@@ -0,0 +1,358 @@
+//! `PS1`: the prompt is a template where backslash escapes are replaced
+//! each time it is shown.
+//!
+//! | escape | replaced by |
+//! |---|---|
+//! | `\u` | user name |
+//! | `\h` / `\H` | host name up to the first `.` / whole |
+//! | `\w` / `\W` | working directory / its last part, `~` for home |
+//! | `\?` | exit status of the last command |
+//! | `\t` / `\A` | local time as `HH:MM:SS` / `HH:MM` |
+//! | `\j` | number of background jobs still running |
+//! | `\g` | git branch, with a `*` when tracked files changed |
+//! | `\$` | `#` for root, `$` otherwise |
+//! | `\n`, `\e`, `\\` | newline, escape (for colors), backslash |
+//! | `\[`, `\]` | around text that takes no room, like colors |
+
+use std::{
+    env,
+    ffi::CStr,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use super::Shell;
+use crate::command_build::expand::Variables;
+
+/// What the prompt looks like without `PS1`.
+pub const DEFAULT_PS1: &str = "[\\W]~$ ";
+/// Shown while a command goes on on the next line, without `PS2`.
+pub const DEFAULT_PS2: &str = "> ";
+
+/// `PS1` with its escapes replaced.
+pub fn render(shell: &Shell, template: &str) -> String {
+    let mut prompt = String::new();
+    let mut chars = template.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            prompt.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => prompt.push_str(&user(shell)),
+            Some('h') => prompt.push_str(host().split('.').next().unwrap_or_default()),
+            Some('H') => prompt.push_str(&host()),
+            Some('w') => prompt.push_str(&tilde(shell, &shell.now_dir)),
+            Some('W') => prompt.push_str(&last_part(shell)),
+            Some('?') => prompt.push_str(&shell.last_status.to_string()),
+            Some('t') => prompt.push_str(&time(true)),
+            Some('A') => prompt.push_str(&time(false)),
+            Some('j') => prompt.push_str(&shell.jobs.running().to_string()),
+            Some('g') => prompt.push_str(&git(&shell.now_dir).unwrap_or_default()),
+            // SAFETY: geteuid can't fail
+            Some('$') => prompt.push(if unsafe { libc::geteuid() } == 0 {
+                '#'
+            } else {
+                '$'
+            }),
+            Some('n') => prompt.push('\n'),
+            Some('e') => prompt.push('\x1b'),
+            Some('\\') => prompt.push('\\'),
+            Some('[' | ']') => {}
+            Some(other) => {
+                prompt.push('\\');
+                prompt.push(other);
+            }
+            None => prompt.push('\\'),
+        }
+    }
+    prompt
+}
+
+fn user(shell: &Shell) -> String {
+    if let Some(user) = shell.var("USER").or_else(|| env::var("LOGNAME").ok()) {
+        return user;
+    }
+    // SAFETY: getpwuid returns null or an entry that stays valid until the
+    // next call, and the name is copied out right away
+    unsafe {
+        let entry = libc::getpwuid(libc::geteuid());
+        if entry.is_null() {
+            return "?".to_string();
+        }
+        CStr::from_ptr((*entry).pw_name)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+fn host() -> String {
+    let mut name = [0u8; 256];
+    // SAFETY: the buffer is as long as we say, and zeroed so the name is
+    // terminated even if it was cut
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len() - 1) } != 0 {
+        return "?".to_string();
+    }
+    CStr::from_bytes_until_nul(&name)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// `dir` with the home directory shown as `~`.
+pub fn tilde(shell: &Shell, dir: &Path) -> String {
+    if let Some(home) = shell.home()
+        && let Ok(rest) = dir.strip_prefix(&home)
+    {
+        if rest.as_os_str().is_empty() {
+            return "~".to_string();
+        }
+        return format!("~/{}", rest.display());
+    }
+    dir.display().to_string()
+}
+
+fn last_part(shell: &Shell) -> String {
+    if shell.home().as_deref() == Some(shell.now_dir.as_path()) {
+        return "~".to_string();
+    }
+    match shell.now_dir.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => shell.now_dir.display().to_string(),
+    }
+}
+
+fn time(seconds: bool) -> String {
+    // SAFETY: a null pointer asks time() for the result only, and tm is
+    // plain data that localtime_r fills in
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        tm
+    };
+    if seconds {
+        format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+    } else {
+        format!("{:02}:{:02}", tm.tm_hour, tm.tm_min)
+    }
+}
+
+/// The git directory of the repository `dir` is in: the first `.git` going
+/// up, which is a file saying `gitdir: path` in worktrees and submodules.
+fn git_dir(dir: &Path) -> Option<(PathBuf, PathBuf)> {
+    for root in dir.ancestors() {
+        let dot_git = root.join(".git");
+        if dot_git.is_dir() {
+            return Some((root.to_path_buf(), dot_git));
+        }
+        if let Ok(file) = fs::read_to_string(&dot_git)
+            && let Some(path) = file.trim().strip_prefix("gitdir:")
+        {
+            return Some((root.to_path_buf(), root.join(path.trim())));
+        }
+    }
+    None
+}
+
+/// `branch`, or `branch*` when a tracked file changed; the first 7 digits
+/// of the commit when the head is detached.
+fn git(dir: &Path) -> Option<String> {
+    let (root, git_dir) = git_dir(dir)?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    let mut branch = match head.strip_prefix("ref: ") {
+        Some(reference) => reference
+            .strip_prefix("refs/heads/")
+            .unwrap_or(reference)
+            .to_string(),
+        None => head.chars().take(7).collect(),
+    };
+    if dirty(&root, &git_dir) {
+        branch.push('*');
+    }
+    Some(branch)
+}
+
+/// What git checks first too: a tracked file that is gone or whose size or
+/// modification time isn't the one recorded in the index.
+fn dirty(root: &Path, git_dir: &Path) -> bool {
+    let Ok(index) = fs::read(git_dir.join("index")) else {
+        return false;
+    };
+    let Some(entries) = index_entries(&index) else {
+        return false;
+    };
+    entries
+        .iter()
+        .any(|entry| match fs::symlink_metadata(root.join(&entry.path)) {
+            Ok(meta) => meta.mtime() as u32 != entry.mtime || meta.size() as u32 != entry.size,
+            Err(_) => true,
+        })
+}
+
+struct IndexEntry {
+    path: String,
+    mtime: u32,
+    size: u32,
+}
+
+fn be32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// The offset number of version 4 paths.
+fn varint(bytes: &[u8], at: &mut usize) -> Option<usize> {
+    let mut byte = *bytes.get(*at)?;
+    *at += 1;
+    let mut value = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        byte = *bytes.get(*at)?;
+        *at += 1;
+        value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+    }
+    Some(value)
+}
+
+/// The entries of a `.git/index` (versions 2 to 4) that can be compared
+/// with the working tree: submodules and `assume-unchanged` or
+/// `skip-worktree` files are left out.
+fn index_entries(index: &[u8]) -> Option<Vec<IndexEntry>> {
+    if index.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = be32(index, 4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    let count = be32(index, 8)?;
+    let mut entries = Vec::new();
+    let mut path = Vec::new();
+    let mut at = 12;
+    for _ in 0..count {
+        let start = at;
+        let mtime = be32(index, start + 8)?;
+        let mode = be32(index, start + 24)?;
+        let size = be32(index, start + 36)?;
+        let flags = u16::from_be_bytes(index.get(start + 60..start + 62)?.try_into().ok()?);
+        at = start + 62;
+        let mut skip = flags & 0x8000 != 0 || mode & 0o170000 == 0o160000;
+        if flags & 0x4000 != 0 {
+            let extended = u16::from_be_bytes(index.get(at..at + 2)?.try_into().ok()?);
+            skip |= extended & 0x4000 != 0;
+            at += 2;
+        }
+        if version == 4 {
+            let strip = varint(index, &mut at)?;
+            path.truncate(path.len().checked_sub(strip)?);
+        } else {
+            path.clear();
+        }
+        let len = index.get(at..)?.iter().position(|&b| b == 0)?;
+        path.extend_from_slice(&index[at..at + len]);
+        at += len + 1;
+        if version != 4 {
+            // entries are padded with NULs to a multiple of 8 bytes
+            at = start + (at - start).div_ceil(8) * 8;
+        }
+        if !skip {
+            entries.push(IndexEntry {
+                path: String::from_utf8_lossy(&path).into_owned(),
+                mtime,
+                size,
+            });
+        }
+    }
+    Some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(version: u32, entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut index = b"DIRC".to_vec();
+        index.extend(version.to_be_bytes());
+        index.extend((entries.len() as u32).to_be_bytes());
+        entries.iter().for_each(|entry| index.extend(entry));
+        index
+    }
+
+    /// The fixed part of an entry, up to its path.
+    fn header(mtime: u32, mode: u32, size: u32, flags: u16) -> Vec<u8> {
+        let mut entry = vec![0; 62];
+        entry[8..12].copy_from_slice(&mtime.to_be_bytes());
+        entry[24..28].copy_from_slice(&mode.to_be_bytes());
+        entry[36..40].copy_from_slice(&size.to_be_bytes());
+        entry[60..62].copy_from_slice(&flags.to_be_bytes());
+        entry
+    }
+
+    fn v2(path: &str, mtime: u32, mode: u32, flags: u16) -> Vec<u8> {
+        let mut entry = header(mtime, mode, path.len() as u32, flags | path.len() as u16);
+        entry.extend(path.as_bytes());
+        entry.resize((entry.len() + 1).div_ceil(8) * 8, 0);
+        entry
+    }
+
+    fn v4(strip: u8, suffix: &str, mtime: u32) -> Vec<u8> {
+        let mut entry = header(mtime, 0o100644, 1, 0);
+        entry.push(strip);
+        entry.extend(suffix.as_bytes());
+        entry.push(0);
+        entry
+    }
+
+    fn paths(entries: Option<Vec<IndexEntry>>) -> Vec<(String, u32)> {
+        entries
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| (entry.path, entry.mtime))
+            .collect()
+    }
+
+    #[test]
+    fn reads_version_2_entries() {
+        let index = index(
+            2,
+            &[
+                v2("Cargo.toml", 7, 0o100644, 0),
+                v2("src/main.rs", 8, 0o100755, 0),
+                v2("vendor/sub", 9, 0o160000, 0),
+                v2("assumed", 10, 0o100644, 0x8000),
+            ],
+        );
+        assert_eq!(
+            paths(index_entries(&index)),
+            [
+                ("Cargo.toml".to_string(), 7),
+                ("src/main.rs".to_string(), 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_version_4_prefix_compressed_paths() {
+        let index = index(
+            4,
+            &[v4(0, "src/a.rs", 1), v4(4, "b.rs", 2), v4(8, "README", 3)],
+        );
+        assert_eq!(
+            paths(index_entries(&index)),
+            [
+                ("src/a.rs".to_string(), 1),
+                ("src/b.rs".to_string(), 2),
+                ("README".to_string(), 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_what_is_not_an_index() {
+        assert!(index_entries(b"nope").is_none());
+        assert!(index_entries(&index(5, &[])).is_none());
+        let mut cut = index(2, &[v2("file", 1, 0o100644, 0)]);
+        cut.truncate(30);
+        assert!(index_entries(&cut).is_none());
+    }
+}
@@ -0,0 +1,141 @@
+//! Startup files: `~/.shurc` for every interactive shell, and a `.shurc`
+//! in the working directory once that directory has been trusted with
+//! `trust`. A project rc is only read from directories listed in
+//! `~/.shu_trusted`, so cloning a repository never runs its code, and only
+//! as long as it is the file that was trusted: once a `git pull` or a
+//! checkout changes it, it has to be trusted again.
+
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use super::{Flow, Shell};
+use crate::command_build::command::Status;
+use crate::command_work::run_file;
+
+pub const RC_FILE: &str = ".shurc";
+const TRUSTED_FILE: &str = ".shu_trusted";
+
+/// Runs a file in the current shell like `source` does: what it defines
+/// stays and a `return` at its top level only ends the file.
+pub fn source_file(shell: &mut Shell, file: &Path) -> io::Result<Status> {
+    shell.depth += 1;
+    let code = run_file(shell, file);
+    shell.depth -= 1;
+    match code {
+        Ok(_) if let Some(Flow::Return(code)) = shell.flow => {
+            shell.flow = None;
+            Ok(code)
+        }
+        code => code,
+    }
+}
+
+/// What an interactive shell reads before the first prompt: `~/.shurc`,
+/// then the `.shurc` of the directory it starts in.
+pub fn startup(shell: &mut Shell) {
+    if let Some(home) = shell.home() {
+        let global = home.join(RC_FILE);
+        if global.is_file() {
+            run_rc(shell, &global);
+        }
+    }
+    directory_rc(shell);
+}
+
+/// Runs the `.shurc` of the working directory if it is trusted, and only
+/// says how to trust it otherwise. The home directory has the global one.
+pub fn directory_rc(shell: &mut Shell) {
+    let rc = shell.now_dir.join(RC_FILE);
+    if !rc.is_file() || shell.home().as_deref() == Some(shell.now_dir.as_path()) {
+        return;
+    }
+    match trusted_stamp(shell, &shell.now_dir) {
+        Some(trusted) if stamp(&shell.now_dir).as_ref() == Some(&trusted) => run_rc(shell, &rc),
+        Some(_) => eprintln!(
+            "shu: {} changed since it was trusted, run `trust` to read it",
+            rc.display()
+        ),
+        None => eprintln!(
+            "shu: {} is not trusted, run `trust` to read it",
+            rc.display()
+        ),
+    }
+}
+
+fn run_rc(shell: &mut Shell, rc: &Path) {
+    if let Err(e) = source_file(shell, rc) {
+        eprintln!("shu: {}: {}", rc.display(), e);
+    }
+    // an `exit` in an rc file shouldn't end the shell before it started
+    shell.exit = None;
+}
+
+fn trusted_file(shell: &Shell) -> Option<PathBuf> {
+    Some(shell.home()?.join(TRUSTED_FILE))
+}
+
+/// What tells whether the `.shurc` of `dir` changed: when it was last
+/// modified, to the nanosecond, and its size. A checkout or a pull sets the
+/// time to when it happened, so a changed file can't pass for the old one.
+fn stamp(dir: &Path) -> Option<String> {
+    let meta = dir.join(RC_FILE).metadata().ok()?;
+    Some(format!(
+        "{}.{:09} {}",
+        meta.mtime(),
+        meta.mtime_nsec(),
+        meta.size()
+    ))
+}
+
+/// The trusted directories with the stamp of their `.shurc`, one
+/// `stamp<TAB>absolute path` per line. The bare paths older versions wrote
+/// don't count any more.
+fn entries(shell: &Shell) -> Vec<(String, PathBuf)> {
+    trusted_file(shell)
+        .and_then(|file| fs::read_to_string(file).ok())
+        .map(|list| {
+            list.lines()
+                .filter_map(|line| line.split_once('\t'))
+                .map(|(stamp, dir)| (stamp.to_string(), PathBuf::from(dir)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The directories whose `.shurc` may run, as long as it stays the same.
+pub fn trusted(shell: &Shell) -> Vec<PathBuf> {
+    entries(shell).into_iter().map(|(_, dir)| dir).collect()
+}
+
+/// The stamp the `.shurc` of `dir` had when it was trusted.
+fn trusted_stamp(shell: &Shell, dir: &Path) -> Option<String> {
+    entries(shell)
+        .into_iter()
+        .find(|(_, trusted)| trusted == dir)
+        .map(|(stamp, _)| stamp)
+}
+
+/// Trusts the `.shurc` `dir` has now, or removes `dir` from the trusted
+/// directories.
+pub fn set_trusted(shell: &Shell, dir: &Path, trust: bool) -> io::Result<()> {
+    let file = trusted_file(shell)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+    let mut list: Vec<(String, PathBuf)> = entries(shell)
+        .into_iter()
+        .filter(|(_, trusted)| trusted != dir)
+        .collect();
+    if trust {
+        let stamp = stamp(dir)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no .shurc to trust"))?;
+        list.push((stamp, dir.to_path_buf()));
+    }
+    let mut text = Vec::new();
+    for (stamp, dir) in list {
+        writeln!(text, "{}\t{}", stamp, dir.display())?;
+    }
+    fs::write(file, text)
+}
@@ -0,0 +1,160 @@
+//! What the interactive shell does with the signals of the terminal:
+//! Ctrl-C (SIGINT) cancels the builtins running for the commands typed at
+//! the prompt, Ctrl-\\ (SIGQUIT) and Ctrl-Z (SIGTSTP) leave it alone.
+//! Programs get them through the process group that has the terminal; one
+//! sent to the shell itself is passed on to that group.
+//!
+//! A signal `trap` set a command for is only noted when it arrives: the
+//! command runs once the command running at the time is over.
+
+use std::{
+    io, mem,
+    os::fd::RawFd,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering},
+};
+
+use crate::command_build::command::Cancel;
+
+/// Signal names `kill` and `trap` know, without the `SIG`.
+pub const SIGNALS: [(&str, libc::c_int); 16] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("WINCH", libc::SIGWINCH),
+];
+
+/// `TERM`, `SIGTERM`, `term` or `15` as a signal number.
+pub fn signal_number(name: &str) -> Option<libc::c_int> {
+    if let Ok(number) = name.parse() {
+        return SIGNALS
+            .iter()
+            .any(|&(_, signal)| signal == number)
+            .then_some(number);
+    }
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|&(_, signal)| signal)
+}
+
+/// The name `SIGNALS` has for `signal`.
+pub fn signal_name(signal: libc::c_int) -> Option<&'static str> {
+    SIGNALS
+        .iter()
+        .find(|&&(_, number)| number == signal)
+        .map(|&(name, _)| name)
+}
+
+/// Whether `signal` ends a program that doesn't handle it.
+pub fn terminates(signal: libc::c_int) -> bool {
+    !matches!(
+        signal,
+        libc::SIGCHLD
+            | libc::SIGCONT
+            | libc::SIGSTOP
+            | libc::SIGTSTP
+            | libc::SIGTTIN
+            | libc::SIGTTOU
+            | libc::SIGWINCH
+    )
+}
+
+/// The signals of the terminal.
+const TERMINAL_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP];
+
+/// The terminal the handler looks the foreground process group up on.
+static TERMINAL: AtomicI32 = AtomicI32::new(-1);
+
+/// Whether the shell handles `TERMINAL_SIGNALS` itself.
+static CAUGHT: AtomicBool = AtomicBool::new(false);
+
+/// Signals that arrived and weren't looked at yet, a bit each.
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+/// Makes the shell handle the signals of the terminal instead of dying or
+/// stopping on them. Programs start with the default handling again, as
+/// `exec` resets handled signals.
+pub fn catch(terminal: Option<RawFd>) {
+    TERMINAL.store(terminal.unwrap_or(-1), Ordering::SeqCst);
+    CAUGHT.store(true, Ordering::SeqCst);
+    for signal in TERMINAL_SIGNALS {
+        let _ = install(signal, handler());
+    }
+}
+
+/// What happens to `signal` for a trap: its command runs (`Some`), it is
+/// ignored (`Some("")`, and so it is by programs), or it is handled as
+/// before any trap (`None`).
+pub fn trap(signal: libc::c_int, command: Option<&str>) -> io::Result<()> {
+    let handler = match command {
+        Some("") => libc::SIG_IGN,
+        Some(_) => handler(),
+        None if CAUGHT.load(Ordering::SeqCst) && TERMINAL_SIGNALS.contains(&signal) => handler(),
+        None => libc::SIG_DFL,
+    };
+    // one that came before the trap isn't for it
+    PENDING.fetch_and(!(1 << signal), Ordering::SeqCst);
+    install(signal, handler)
+}
+
+/// The signals that arrived since the last call.
+pub fn arrived() -> Vec<libc::c_int> {
+    let pending = PENDING.swap(0, Ordering::SeqCst);
+    (1..64)
+        .filter(|signal| pending & 1 << signal != 0)
+        .collect()
+}
+
+fn handler() -> libc::sighandler_t {
+    handle as extern "C" fn(libc::c_int) as libc::sighandler_t
+}
+
+fn install(signal: libc::c_int, handler: libc::sighandler_t) -> io::Result<()> {
+    // SAFETY: the handler only touches atomics and makes async-signal-safe
+    // calls. Without SA_RESTART a builtin waiting for the terminal wakes up
+    // to see it was cancelled.
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handler;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signal, &action, ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+extern "C" fn handle(signal: libc::c_int) {
+    if (1..64).contains(&signal) {
+        PENDING.fetch_or(1 << signal, Ordering::SeqCst);
+    }
+    if signal == libc::SIGINT {
+        Cancel::default().set();
+    }
+    let tty = TERMINAL.load(Ordering::SeqCst);
+    if tty < 0 || !TERMINAL_SIGNALS.contains(&signal) {
+        return;
+    }
+    // SAFETY: tcgetpgrp, getpgrp and kill are async-signal-safe
+    unsafe {
+        let group = libc::tcgetpgrp(tty);
+        if group > 0 && group != libc::getpgrp() {
+            libc::kill(-group, signal);
+        }
+    }
+}
@@ -1,209 +1,235 @@
-use cmd::command_work::todo;
-use std::{
-    env,
-    fs::{self, File, OpenOptions},
-    io::{self, Write, stdin, stdout},
-    path::PathBuf,
-    sync::{Arc, Mutex, RwLock},
-    thread::{self, JoinHandle},
+use cmd::{
+    command_build::{
+        command::{FAILURE, INTERRUPTED, NOT_FOUND, SUCCESS, USAGE},
+        expand::Variables,
+        parse::parse_line,
+    },
+    command_work::{run_file, run_signal_traps, run_trap, todo},
+    line_editor::{Completion, Editor, Helper, Hit, Input, Span},
+    shell::{
+        Shell, complete, highlight,
+        history::{self, Entry},
+        jobs,
+        prompt::{self, DEFAULT_PS1, DEFAULT_PS2},
+        rc, signals,
+    },
 };
+use std::{cell::OnceCell, env, io, path::Path, process, time::Instant};
 
-fn report_code(command: &str, code: bool, shu_his: Arc<Mutex<File>>) -> io::Result<()> {
-    if !code {
-        println!("ERROR!\nexit code: 1")
+/// Shows a failure and records the command in the history file.
+fn report_code(shell: &Shell, entry: Entry) {
+    if entry.status != SUCCESS {
+        println!("ERROR!\nexit code: {}", entry.status)
     };
-    if let Ok(mut f) = shu_his.lock() {
-        writeln!(f, "{} {}", command, if code { "" } else { "ERROR" })?
+    if let Err(e) = history::append(&shell.history, &entry) {
+        eprintln!("shu: history file error: {}", e);
     }
-    Ok(())
-}
-
-fn process_terminated() {
-    println!("tranks for using our terminal!");
 }
 
 //TODO cross-platform
 //TODO tests
 //TODO pass programm for modules
 
-fn showdir(now_dir: &Arc<RwLock<PathBuf>>, fulldir: bool) -> bool {
-    if let Ok(pth) = now_dir.read() {
-        if fulldir {
-            println!("{}", pth.display());
-        } else if let Some(name) = pth.file_stem() {
-            print!("[{}]~$ ", name.display());
-        } else {
-            print!("[???]~$ ");
-        }
-        let _ = stdout().flush();
-        true
-    } else {
-        false
-    }
+/// `PS1` (or `PS2`, for the next lines of an unfinished command) with its
+/// escapes replaced.
+fn prompt(shell: &Shell, name: &str, default: &str) -> String {
+    let template = shell.var(name).unwrap_or_else(|| default.to_string());
+    prompt::render(shell, &template)
 }
 
-fn full_dir(now_dir: &Arc<RwLock<PathBuf>>) -> Option<PathBuf> {
-    if let Ok(path) = now_dir.read() {
-        Some(path.clone())
-    } else {
-        None
+/// The line with its history references replaced, `None` if it has none.
+fn expand_history(shell: &Shell, line: &str) -> Result<Option<String>, String> {
+    if !line.contains('!') && !line.starts_with('^') {
+        return Ok(None);
     }
+    let entries =
+        history::load(&shell.history).map_err(|e| format!("history file error: {}", e))?;
+    history::expand(line, &entries)
 }
 
-enum NewDir<'a> {
-    StrDir(&'a str),
-    PathDir(PathBuf),
+/// What the line editor asks about the command being typed.
+struct ShellHelper<'s> {
+    shell: &'s Shell,
+    /// The history file, read on the first Ctrl-R.
+    history: OnceCell<Vec<Entry>>,
 }
 
-fn changedir(now_dir: &Arc<RwLock<PathBuf>>, new_dir: NewDir) -> bool {
-    if let Ok(mut path) = now_dir.write() {
-        match new_dir {
-            NewDir::StrDir(new_dir) => match path.join(new_dir).canonicalize() {
-                Ok(abs) => {
-                    *path = abs;
-                    true
-                }
-                Err(e) => {
-                    eprintln!("shu: cd: {}", e);
-                    false
-                }
-            },
-            NewDir::PathDir(new_dir) => {
-                *path = new_dir;
-                true
-            }
+impl<'s> ShellHelper<'s> {
+    fn new(shell: &'s Shell) -> Self {
+        Self {
+            shell,
+            history: OnceCell::new(),
         }
-    } else {
-        false
     }
 }
 
-fn clear_history() -> io::Result<()> {
-    File::create(".shu_history")?;
-    Ok(())
+/// Where and how a history entry ran, as far as the file knows.
+fn detail(shell: &Shell, entry: &Entry) -> String {
+    let mut parts = Vec::new();
+    if !entry.cwd.as_os_str().is_empty() {
+        parts.push(prompt::tilde(shell, &entry.cwd));
+    }
+    parts.push(match entry.status {
+        SUCCESS => "ok".to_string(),
+        status => format!("exit {}", status),
+    });
+    if entry.time > 0 {
+        parts.push(history::format_time(entry.time));
+    }
+    parts.join("  ")
 }
 
-fn main() -> io::Result<()> {
-    let mut command = String::new();
-    let shu_his = Arc::new(Mutex::new(
-        OpenOptions::new()
-            .create(true)
-            .append(true)
-            .read(true)
-            .open(".shu_history")?,
-    ));
-    let mut threads: Vec<JoinHandle<()>> = Vec::new();
-    let mut thread_mode = false;
-    let now_dir = Arc::new(RwLock::new(env::current_dir()?));
-    let mut iter = 0;
-    'mainloop: loop {
-        if iter % 10 == 0 && !threads.is_empty() {
-            iter = 0;
-            threads.retain(|th| th.is_finished());
-        }
-        iter += 1;
+impl Helper for ShellHelper<'_> {
+    // unclosed quotes and here-documents go on on the next lines
+    fn incomplete(&self, line: &str) -> bool {
+        parse_line(line.trim()).is_err_and(|e| e.is_incomplete())
+    }
+
+    fn complete(&self, line: &str, cursor: usize) -> Completion {
+        complete::complete(self.shell, &line[..cursor])
+    }
+
+    fn highlight(&self, line: &str) -> Vec<Span> {
+        highlight::highlight(self.shell, line)
+    }
 
-        showdir(&now_dir, false);
-        let _ = stdin().read_line(&mut command);
+    fn search(&self, query: &str, here: bool) -> Vec<Hit> {
+        let entries = self
+            .history
+            .get_or_init(|| history::load(&self.shell.history).unwrap_or_default());
+        let cwd = here.then_some(self.shell.now_dir.as_path());
+        history::search(entries, query, cwd)
+            .into_iter()
+            .map(|(entry, matches)| Hit {
+                text: entry.command.clone(),
+                matches,
+                detail: detail(self.shell, entry),
+            })
+            .collect()
+    }
+}
+
+fn interactive(shell: &mut Shell) -> io::Result<()> {
+    let mut editor = Editor::new();
+    // the commands of earlier sessions, for Up and Down
+    match history::load(&shell.history) {
+        Ok(entries) => entries
+            .iter()
+            .for_each(|entry| editor.add_history(&entry.command)),
+        Err(e) => eprintln!("shu: history file error: {}", e),
+    }
+    shell.terminal = jobs::take_terminal();
+    signals::catch(shell.terminal);
+    loop {
+        run_signal_traps(shell);
+        // jobs that stopped or ended since the last prompt
+        for (job, state) in shell.jobs.report(false) {
+            println!("{}", job.line(state, false));
+        }
+        let ps1 = prompt(shell, "PS1", DEFAULT_PS1);
+        let ps2 = prompt(shell, "PS2", DEFAULT_PS2);
+        let command = match editor.read_line(&ps1, &ps2, &ShellHelper::new(shell))? {
+            Input::Line(command) => command,
+            Input::Interrupted => {
+                shell.last_status = INTERRUPTED;
+                continue;
+            }
+            Input::Eof => break,
+        };
 
-        let mut command_tr = command.trim();
+        let command_tr = command.trim();
         if command_tr.is_empty() {
             continue;
         }
-        if command_tr.ends_with('&') {
-            thread_mode = true;
-            command_tr = command_tr.trim_matches('&');
-        }
-        let mut code;
-        for trimmed_command in command_tr.split("&&").map(|x| x.trim()) {
-            let dir_clone = if let Some(dir) = full_dir(&now_dir) {
-                dir
-            } else {
-                println!("error with getting dir!");
+        // `!!`, `!$`, `^old^new` and the like, shown before they run
+        let expanded = match expand_history(shell, command_tr) {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                eprintln!("shu: {}", e);
+                shell.last_status = FAILURE;
                 continue;
-            };
-            code = true;
-            let shu_arc = Arc::clone(&shu_his);
-            let now_dir_arc = Arc::clone(&now_dir);
-            match trimmed_command {
-                "exit" => {
-                    process_terminated();
-                    report_code("exit", true, shu_arc)?;
-                    break 'mainloop;
-                }
-                "history" => {
-                    match fs::read_to_string(".shu_history") {
-                        Ok(his) => {
-                            print!("{}", his);
-                            code = true;
-                        }
-                        Err(e) => {
-                            eprintln!("shu: history file error: {}", e);
-                            code = false;
-                        }
-                    }
-                    report_code("history", code, shu_arc)?;
-                }
-                "clearHIS" => {
-                    let code = clear_history().is_ok();
-                    report_code("clearHis", code, shu_arc)?;
-                }
-                "clear" => {
-                    print!("{}[2J", 27 as char);
-                    print!("{}[1;1H", 27 as char);
-                    report_code("clear", true, shu_arc)?;
-                }
-                i if i.starts_with("cd ") || i == "cd" => {
-                    code = match i.split_once(' ') {
-                        Some((_, new_dir)) if !new_dir.is_empty() => {
-                            changedir(&now_dir, NewDir::StrDir(new_dir))
-                        }
-                        _ => {
-                            if let Some(home) = env::home_dir() {
-                                changedir(&now_dir, NewDir::PathDir(home))
-                            } else {
-                                false
-                            }
-                        }
-                    };
-                    report_code(i, code, shu_arc)?;
-                }
-                "pwd" => {
-                    code = showdir(&now_dir_arc, true);
-                    report_code("pwd", code, shu_arc)?;
-                }
-
-                _ => {
-                    if !thread_mode {
-                        code = todo(trimmed_command, dir_clone);
-                        report_code(trimmed_command, code, shu_arc)?;
-                    } else {
-                        let command_clone = trimmed_command.to_owned();
-                        let thread_shu = Arc::clone(&shu_his);
-                        let thread_dir = Arc::clone(&now_dir);
-                        let answer = thread::spawn(move || {
-                            let code = todo(&command_clone, dir_clone);
-                            if let Err(e) = report_code(&command_clone, code, thread_shu) {
-                                eprintln!("shu: error with write: {}", e);
-                            }
-                            println!("ghost process ends: {}", &command_clone);
-                            showdir(&thread_dir, false);
-                        });
-                        threads.push(answer);
-                        thread_mode = false;
-                    }
-                }
             }
-            if !code {
-                break;
+        };
+        let command_tr = match &expanded {
+            Some(expanded) => {
+                println!("{}", expanded);
+                expanded.as_str()
             }
+            None => command_tr,
+        };
+        editor.add_history(command_tr);
+        let time = history::now();
+        let cwd = shell.now_dir.clone();
+        let started = Instant::now();
+        shell.control.cancel().clear();
+        let status = todo(shell, command_tr);
+        if status == INTERRUPTED {
+            // after the ^C of the terminal
+            println!();
         }
-        command.clear();
-    }
-    for thread in threads {
-        if thread.join().is_err() {
-            eprintln!("error with thread")
+        report_code(
+            shell,
+            Entry {
+                time,
+                duration: started.elapsed(),
+                status,
+                cwd,
+                command: command_tr.to_string(),
+            },
+        );
+        if shell.exit.is_some() {
+            break;
         }
     }
     Ok(())
 }
+
+/// `terminal` alone is interactive, `terminal -c 'commands' [$0 args...]`
+/// runs a string and `terminal script.shu [args...]` a file (also what a
+/// `#!` line pointing at this binary ends up calling). Only the interactive
+/// shell reads the `.shurc` files, unless `--norc` is given.
+fn main() -> io::Result<()> {
+    let mut args: Vec<String> = env::args().collect();
+    let norc = args.get(1).is_some_and(|arg| arg == "--norc");
+    if norc {
+        args.remove(1);
+    }
+    let cwd = env::current_dir()?;
+    let history = history::default_location(&cwd);
+    let mut shell = Shell::new(cwd, history);
+    let code = match args.get(1).map(String::as_str) {
+        None => {
+            if !norc {
+                rc::startup(&mut shell);
+            }
+            interactive(&mut shell)?;
+            shell.last_status
+        }
+        Some("-c") => match args.get(2) {
+            Some(command) => {
+                if args.len() > 3 {
+                    shell.args = args[3..].to_vec();
+                }
+                todo(&mut shell, command)
+            }
+            None => {
+                eprintln!("shu: -c: option requires an argument");
+                USAGE
+            }
+        },
+        Some(script) => {
+            shell.args = args[1..].to_vec();
+            match run_file(&mut shell, Path::new(script)) {
+                Ok(code) => code,
+                Err(e) => {
+                    eprintln!("shu: {}: {}", script, e);
+                    NOT_FOUND
+                }
+            }
+        }
+    };
+    shell.last_status = shell.exit.unwrap_or(code);
+    run_trap(&mut shell, "EXIT");
+    shell.jobs.finish();
+    process::exit(shell.exit.unwrap_or(shell.last_status));
+}